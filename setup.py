@@ -1,3 +1,5 @@
+import json
+
 import setuptools.command.build_py
 
 from setuptools import setup
@@ -37,6 +39,17 @@ class BuildCommand(setuptools.command.build_py.build_py):
         tree_sitter.Language.build_library('./zeekscript/zeek-language.so',
                                            ['tree-sitter-zeek'])
 
+        # Record the grammar's version next to the bindings, for
+        # zeekscript.metadata().
+        try:
+            with open('tree-sitter-zeek/package.json') as hdl:
+                version = json.load(hdl).get('version', '')
+        except (OSError, ValueError):
+            version = ''
+
+        with open('./zeekscript/zeek-language.version', 'w') as hdl:
+            hdl.write(version + '\n')
+
 
 setup(
     name='zeekscript',
@@ -51,7 +64,7 @@ setup(
 
     scripts=['zeek-format', 'zeek-script'],
     packages=['zeekscript'],
-    package_data={'zeekscript': ['zeek-language.so']},
+    package_data={'zeekscript': ['zeek-language.so', 'zeek-language.version']},
 
     cmdclass={
         'build_py': BuildCommand,
@@ -61,7 +74,7 @@ setup(
     distclass=BinaryDistribution,
 
     setup_requires=['tree_sitter'],
-    install_requires=['tree_sitter'],
+    install_requires=['tree_sitter', 'tomli; python_version < "3.11"'],
     python_requires='>3.7.0',
 
     classifiers=[
@@ -4,6 +4,9 @@ import sys
 
 import test_formatting
 import test_dir_recursion
+import test_cli
+import test_lsp
+import test_fuzz
 import test_pylint
 
 if __name__ == '__main__':
@@ -12,5 +15,8 @@ if __name__ == '__main__':
     sys.exit(not all((
         test_formatting.test(),
         test_dir_recursion.test(),
+        test_cli.test(),
+        test_lsp.test(),
+        test_fuzz.test(),
         test_pylint.test(),
         )))
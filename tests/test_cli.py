@@ -0,0 +1,293 @@
+#! /usr/bin/env python
+import argparse
+import io
+import os
+import shutil
+import sys
+import unittest
+import unittest.mock
+
+from os.path import join
+
+TESTS = os.path.dirname(os.path.realpath(__file__))
+ROOT = os.path.normpath(join(TESTS, '..'))
+DATA = os.path.normpath(join(TESTS, 'data'))
+
+# Prepend the tree's root folder to the module searchpath so we find zeekscript
+# via it. This allows tests to run without package installation. (We do need a
+# package build though, so the .so bindings library gets created.)
+sys.path.insert(0, ROOT)
+
+import zeekscript
+
+class TestFormatCmd(unittest.TestCase):
+
+    def setUp(self):
+        shutil.rmtree('cli', ignore_errors=True)
+        os.makedirs('cli')
+
+        shutil.copy(join(DATA, 'test1.zeek'), join('cli', 'test1.zeek'))
+        with open(join('cli', 'broken.zeek'), 'w') as hdl:
+            hdl.write('event zeek_init() { foo)(); }\n')
+
+    def tearDown(self):
+        shutil.rmtree('cli', ignore_errors=True)
+
+    def run_cmd(self, *cmdline):
+        parser = argparse.ArgumentParser()
+        zeekscript.add_format_cmd(parser)
+        args = parser.parse_args(cmdline)
+
+        with unittest.mock.patch('sys.stdout', new=io.StringIO()) as out, \
+             unittest.mock.patch('sys.stderr', new=io.StringIO()) as err:
+            ret = args.run_cmd(args)
+            return ret, out.getvalue(), err.getvalue()
+
+    def _write(self, fname, content):
+        with open(fname, 'w') as hdl:
+            hdl.write(content)
+
+    def _stdin(self, content):
+        # Scripts come from stdin's underlying binary buffer.
+        if isinstance(content, str):
+            content = content.encode('UTF-8')
+        return unittest.mock.patch('sys.stdin', new=io.TextIOWrapper(io.BytesIO(content)))
+
+    def test_success(self):
+        ret, out, err = self.run_cmd('-i', join('cli', 'test1.zeek'))
+        self.assertEqual(ret, zeekscript.EXIT_OK)
+        self.assertEqual(out, '1 file processed: 1 formatted, 0 unchanged, 0 failed\n')
+        self.assertEqual(err, '')
+
+    def test_in_place_alias(self):
+        ret, out, _ = self.run_cmd('--in-place', join('cli', 'test1.zeek'))
+        self.assertEqual(ret, zeekscript.EXIT_OK)
+        self.assertEqual(out, '1 file processed: 1 formatted, 0 unchanged, 0 failed\n')
+
+    def test_parse_error(self):
+        fname = join('cli', 'broken.zeek')
+        ret, _, err = self.run_cmd('-i', fname)
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+        self.assertEqual(err, '{}: cannot parse line 0, col 23: ")"\n'.format(fname))
+
+    def test_missing_file(self):
+        # A file that vanishes after argument processing can't get read:
+        fname = join('cli', 'test1.zeek')
+        with unittest.mock.patch('os.path.isfile', return_value=True):
+            os.unlink(fname)
+            ret, _, err = self.run_cmd('-i', fname)
+
+        self.assertEqual(ret, zeekscript.EXIT_FILE_ERROR)
+        self.assertTrue(err.startswith(fname + ': error: '))
+
+    def test_failure_continues(self):
+        ret, out, err = self.run_cmd('-i', join('cli', 'broken.zeek'), join('cli', 'test1.zeek'))
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+        self.assertEqual(out, '2 files processed: 1 formatted, 0 unchanged, 1 failed\n')
+        self.assertEqual(len(err.splitlines()), 1)
+
+        with open(join(DATA, 'test1.zeek.out')) as hdl1, \
+             open(join('cli', 'test1.zeek')) as hdl2:
+            self.assertEqual(hdl1.read(), hdl2.read())
+
+    def test_parallel(self):
+        for idx in range(4):
+            shutil.copy(join(DATA, 'test1.zeek'), join('cli', 'copy{}.zeek'.format(idx)))
+
+        ret, out, _ = self.run_cmd('-i', '-r', '-j', '3', 'cli')
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+        self.assertEqual(out, '6 files processed: 5 formatted, 0 unchanged, 1 failed\n')
+
+        # A second run leaves all but the broken script unchanged:
+        ret, out, _ = self.run_cmd('-i', '-r', '-j', '3', 'cli')
+        self.assertEqual(out, '6 files processed: 0 formatted, 5 unchanged, 1 failed\n')
+
+    def test_parallel_stdin(self):
+        # Worker processes don't see our stdin, so it still gets checked
+        # along with the files.
+        fname = join('cli', 'test1.zeek')
+        shutil.copy(join(DATA, 'test1.zeek.out'), fname)
+
+        with open(join(DATA, 'test1.zeek'), 'rb') as hdl, self._stdin(hdl.read()):
+            ret, _, err = self.run_cmd('--check', '-j', '2', '-', fname)
+
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+        self.assertEqual(err, 'stdin is not formatted\n')
+
+    def test_symlink_loop(self):
+        os.makedirs(join('cli', 'sub'))
+        try:
+            os.symlink(os.path.abspath('cli'), join('cli', 'sub', 'loop'))
+        except (OSError, NotImplementedError):
+            self.skipTest('symlinks not supported')
+
+        ret, out, _ = self.run_cmd('-i', '-r', 'cli')
+        self.assertEqual(out, '2 files processed: 1 formatted, 0 unchanged, 1 failed\n')
+
+    def test_check(self):
+        fname = join('cli', 'test1.zeek')
+
+        ret, out, err = self.run_cmd('--check', fname)
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+        self.assertEqual(out, '')
+        self.assertEqual(err, '"{}" is not formatted\n'.format(fname))
+
+        shutil.copy(join(DATA, 'test1.zeek.out'), fname)
+        ret, out, err = self.run_cmd('--check', fname)
+        self.assertEqual(ret, zeekscript.EXIT_OK)
+        self.assertEqual((out, err), ('', ''))
+
+    def test_diff(self):
+        fname = join('cli', 'test1.zeek')
+        parser = argparse.ArgumentParser()
+        zeekscript.add_format_cmd(parser)
+        args = parser.parse_args(['--diff', fname])
+
+        # The diff goes out in binary, so we need stdout's buffer.
+        with unittest.mock.patch('sys.stdout', new=io.TextIOWrapper(io.BytesIO())) as out, \
+             unittest.mock.patch('sys.stderr', new=io.StringIO()) as err:
+            ret = args.run_cmd(args)
+            out.flush()
+            diff = out.buffer.getvalue().decode('UTF-8')
+
+        self.assertEqual(ret, zeekscript.EXIT_OK)
+        self.assertEqual(err.getvalue(), '')
+        self.assertTrue(diff.startswith('--- a/{}\n+++ b/{}\n'.format(fname, fname)))
+
+        with open(join(DATA, 'test1.zeek'), 'rb') as hdl1, open(fname, 'rb') as hdl2:
+            self.assertEqual(hdl1.read(), hdl2.read())
+
+    def test_line_length(self):
+        fname = join('cli', 'long.zeek')
+        with open(fname, 'w') as hdl:
+            hdl.write('global x = ' + ' + '.join(['foo'] * 16) + ';\n')
+
+        ret, _, _ = self.run_cmd('--check', '--line-length', '120', fname)
+        self.assertEqual(ret, zeekscript.EXIT_OK)
+
+        ret, _, err = self.run_cmd('--check', fname)
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+        self.assertEqual(err, '"{}" is not formatted\n'.format(fname))
+
+    def test_invalid_line_length(self):
+        ret, _, err = self.run_cmd('--line-length', '0', join('cli', 'test1.zeek'))
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+        self.assertEqual(err, 'error: --line-length requires a positive number\n')
+
+    def test_config_file(self):
+        # A 105-character line fits the configured limit, and nested
+        # configuration files take precedence over those further up.
+        os.makedirs(join('cli', 'sub', 'deeper'))
+        fnames = [join('cli', 'long.zeek'), join('cli', 'sub', 'long.zeek'),
+                  join('cli', 'sub', 'deeper', 'long.zeek')]
+        for fname in fnames:
+            self._write(fname, 'global x = ' + ' + '.join(['foo'] * 16) + ';\n')
+
+        self._write(join('cli', '.zeek-format.toml'), 'max_line_len = 120\n')
+        self._write(join('cli', 'sub', '.zeek-format.toml'), '# Back to the default\n')
+
+        ret, _, err = self.run_cmd('--check', *fnames)
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+        self.assertEqual(err.splitlines(), [
+            '"{}" is not formatted'.format(fnames[1]),
+            '"{}" is not formatted'.format(fnames[2])])
+
+        # Command line flags win over configuration files:
+        ret, _, _ = self.run_cmd('--check', '--line-length', '120', fnames[1])
+        self.assertEqual(ret, zeekscript.EXIT_OK)
+        ret, _, _ = self.run_cmd('--check', '--line-length', '80', fnames[0])
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+
+    def test_stdin_filepath(self):
+        self._write(join('cli', '.zeek-format.toml'), 'max_line_len = 120\n')
+        content = 'global x = ' + ' + '.join(['foo'] * 16) + ';\n'
+
+        def run(*flags):
+            with self._stdin(content):
+                ret, _, _ = self.run_cmd('--check', *flags, '-')
+            return ret
+
+        # The configuration applies to a script in that directory, whether
+        # or not it exists.
+        self.assertEqual(run('--stdin-filepath', join('cli', 'long.zeek')), zeekscript.EXIT_OK)
+        self.assertEqual(run(), zeekscript.EXIT_ERROR)
+
+    def test_stdin_bytes(self):
+        # Content that isn't valid UTF-8 passes through as in files.
+        content = b'global a = "\xff";\n'
+        with open(join('cli', 'latin1.zeek'), 'wb') as hdl:
+            hdl.write(content)
+
+        with self._stdin(content):
+            ret, _, err = self.run_cmd('--check', '-')
+        self.assertEqual((ret, err), (zeekscript.EXIT_OK, ''))
+
+        ret, _, err = self.run_cmd('--check', join('cli', 'latin1.zeek'))
+        self.assertEqual((ret, err), (zeekscript.EXIT_OK, ''))
+
+    def test_config_file_error(self):
+        self._write(join('cli', '.zeek-format.toml'), 'max_line_length = 120\n')
+
+        ret, _, err = self.run_cmd('--check', join('cli', 'test1.zeek'))
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+        self.assertEqual(err, 'error: {}: unknown formatting option "max_line_length"\n'.format(
+            os.path.abspath(join('cli', '.zeek-format.toml'))))
+
+    def test_editorconfig(self):
+        os.makedirs(join('cli', 'sub'))
+        fnames = [join('cli', 'short.zeek'), join('cli', 'sub', 'short.zeek')]
+
+        self._write(join('cli', '.editorconfig'),
+                    'root = true\n\n[*]\nindent_style = space\nindent_size = 4\n')
+        self._write(join('cli', 'sub', '.editorconfig'), '[*.zeek]\nindent_size = 2\n')
+
+        def run(*flags):
+            for fname in fnames:
+                with open(fname, 'wb') as hdl:
+                    hdl.write(b'event zeek_init() { print 1; }\n')
+
+            ret, _, _ = self.run_cmd('-i', *flags, *fnames)
+            self.assertEqual(ret, zeekscript.EXIT_OK)
+
+            res = []
+            for fname in fnames:
+                with open(fname, 'rb') as hdl:
+                    res.append(hdl.read())
+            return res
+
+        self.assertEqual(run('--editorconfig'), [
+            b'event zeek_init()\n    {\n    print 1;\n    }\n',
+            b'event zeek_init()\n  {\n  print 1;\n  }\n'])
+
+        # Without the flag, .editorconfig files don't matter:
+        self.assertEqual(run(), [b'event zeek_init()\n\t{\n\tprint 1;\n\t}\n'] * 2)
+
+        # Configuration files override .editorconfig:
+        self._write(join('cli', 'sub', '.zeek-format.toml'), 'indent = "\\t"\n')
+        self.assertEqual(run('--editorconfig'), [
+            b'event zeek_init()\n    {\n    print 1;\n    }\n',
+            b'event zeek_init()\n\t{\n\tprint 1;\n\t}\n'])
+
+    def test_inplace_and_diff(self):
+        ret, _, err = self.run_cmd('-i', '--diff', join('cli', 'test1.zeek'))
+        self.assertEqual(ret, zeekscript.EXIT_ERROR)
+        self.assertEqual(err, 'error: --inplace cannot be combined with --check or --diff\n')
+
+    def test_unsupported_file(self):
+        ret, _, err = self.run_cmd('-i', join('cli', 'nosuchfile.zeek'))
+        self.assertEqual(ret, zeekscript.EXIT_OK)
+        self.assertEqual(err, 'warning: skipping "{}"; not a supported file type\n'.format(
+            join('cli', 'nosuchfile.zeek')))
+
+
+def test():
+    """Entry point for testing this module.
+
+    Returns True if successful, False otherwise.
+    """
+    res = unittest.main(sys.modules[__name__], verbosity=0, exit=False)
+    # This is how unittest.main() implements the exit code itself:
+    return res.result.wasSuccessful()
+
+if __name__ == '__main__':
+    sys.exit(not test())
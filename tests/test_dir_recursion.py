@@ -54,7 +54,7 @@ class TestRecursion(unittest.TestCase):
              unittest.mock.patch('sys.stderr', new=io.StringIO()) as err:
             ret = args.run_cmd(args)
             self.assertEqual(ret, 0)
-            self.assertEqual(out.getvalue(), '4 files processed, 0 errors\n')
+            self.assertEqual(out.getvalue(), '4 files processed: 4 formatted, 0 unchanged, 0 failed\n')
 
         self.assertEqualContent(
             join(DATA, 'test1.zeek.out'), join('a', 'test1.zeek'))
@@ -68,6 +68,79 @@ class TestRecursion(unittest.TestCase):
         self.assertNotEqualContent(
             join(DATA, 'test1.zeek.out'), join('a', 'b', 'test3.txt'))
 
+    def test_recursive_check(self):
+        parser = argparse.ArgumentParser()
+        zeekscript.add_format_cmd(parser)
+        args = parser.parse_args(['--check', '-r', 'a'])
+
+        with unittest.mock.patch('sys.stdout', new=io.StringIO()) as out, \
+             unittest.mock.patch('sys.stderr', new=io.StringIO()) as err:
+            ret = args.run_cmd(args)
+            self.assertEqual(ret, 1)
+            self.assertEqual(out.getvalue(), '')
+            self.assertEqual(len(err.getvalue().splitlines()), 4)
+
+        # Checking must not modify anything:
+        self.assertEqualContent(join(DATA, 'test1.zeek'), join('a', 'test1.zeek'))
+
+        # Once formatted, the check passes.
+        args = parser.parse_args(['-i', '-r', 'a'])
+        with unittest.mock.patch('sys.stdout', new=io.StringIO()):
+            args.run_cmd(args)
+
+        args = parser.parse_args(['--check', '-r', 'a'])
+        with unittest.mock.patch('sys.stdout', new=io.StringIO()) as out, \
+             unittest.mock.patch('sys.stderr', new=io.StringIO()) as err:
+            ret = args.run_cmd(args)
+            self.assertEqual(ret, 0)
+            self.assertEqual(err.getvalue(), '')
+
+    def test_ignore_file(self):
+        with open(join('a', '.zeekformatignore'), 'w') as hdl:
+            hdl.write('# Leave these alone\nb/\ntest*.zeek\n!test2.zeek\n')
+
+        # Nested ignore files don't matter inside ignored directories:
+        with open(join('a', 'b', '.zeekformatignore'), 'w') as hdl:
+            hdl.write('!*.zeek\n')
+
+        self.assertEqual(zeekscript.collect_zeek_files('a'), [join('a', 'test2.zeek')])
+
+        parser = argparse.ArgumentParser()
+        zeekscript.add_format_cmd(parser)
+        args = parser.parse_args(['-i', '-r', '--verbose', 'a'])
+
+        with unittest.mock.patch('sys.stdout', new=io.StringIO()) as out, \
+             unittest.mock.patch('sys.stderr', new=io.StringIO()) as err:
+            ret = args.run_cmd(args)
+            self.assertEqual(ret, 0)
+            self.assertEqual(out.getvalue(), '1 file processed: 1 formatted, 0 unchanged, 0 failed\n')
+
+            ignore_file = join('a', '.zeekformatignore')
+            self.assertEqual(err.getvalue().splitlines(), [
+                'skipping "{}", ignored via {}:3: test*.zeek'.format(join('a', 'test1.zeek'), ignore_file),
+                'skipping "{}", ignored via {}:2: b/'.format(join('a', 'b'), ignore_file),
+            ])
+
+        self.assertNotEqualContent(join(DATA, 'test1.zeek.out'), join('a', 'test1.zeek'))
+        self.assertEqualContent(join(DATA, 'test1.zeek.out'), join('a', 'test2.zeek'))
+
+    def test_nested_ignore_file(self):
+        with open(join('a', 'b', '.zeekformatignore'), 'w') as hdl:
+            hdl.write('/c/\n')
+        with open(join('a', '.zeekformatignore'), 'w') as hdl:
+            hdl.write('test4.zeek\n')
+
+        self.assertEqual(zeekscript.collect_zeek_files('a'),
+                         [join('a', 'test1.zeek'), join('a', 'test2.zeek')])
+
+        # The deeper file takes precedence:
+        with open(join('a', 'b', '.zeekformatignore'), 'w') as hdl:
+            hdl.write('!test4.zeek\n')
+
+        self.assertEqual(zeekscript.collect_zeek_files('a'), [
+            join('a', 'test1.zeek'), join('a', 'test2.zeek'),
+            join('a', 'b', 'test4.zeek'), join('a', 'b', 'c', 'test5.zeek')])
+
     def test_recurse_inplace(self):
         parser = argparse.ArgumentParser()
         zeekscript.add_format_cmd(parser)
@@ -99,7 +172,7 @@ class TestRecursion(unittest.TestCase):
              unittest.mock.patch('sys.stderr', new=io.StringIO()) as err:
             ret = args.run_cmd(args)
             self.assertEqual(ret, 1)
-            self.assertEqual(err.getvalue(), 'error: recursive file processing requires --inplace\n')
+            self.assertEqual(err.getvalue(), 'error: recursive file processing requires --inplace, --check, or --diff\n')
 
 
 def test():
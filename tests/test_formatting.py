@@ -2,8 +2,12 @@
 import io
 import os
 import pathlib
+import pickle
+import shutil
 import sys
+import tempfile
 import unittest
+import unittest.mock
 
 TESTS = os.path.dirname(os.path.realpath(__file__))
 ROOT = os.path.normpath(os.path.join(TESTS, '..'))
@@ -38,17 +42,210 @@ class TestFormatting(unittest.TestCase):
 
         return buf.getvalue()
 
+    def assertFormattingEqual(self, baseline, result):
+        # Like assertEqual, but shows a readable diff of the script content.
+        if baseline != result:
+            self.fail('formatting differs from baseline:\n' +
+                      zeekscript.IdempotencyError(baseline, result).diff())
+
     def test_file_formatting(self):
         input, baseline = self._get_input_and_baseline('test1.zeek')
 
         # Format the input data and compare to baseline:
         result1 = self._format(input)
-        self.assertEqual(baseline, result1)
+        self.assertFormattingEqual(baseline, result1)
 
         # Format the result again. There should be no change.
         result2 = self._format(result1)
+        self.assertFormattingEqual(baseline, result2)
+
+    def test_constructs(self):
+        # A tour of the constructs common in Zeek's base scripts, each with
+        # layout problems to fix.
+        input, baseline = self._get_input_and_baseline('constructs.zeek')
+
+        result = self._format(input)
+        self.assertFormattingEqual(baseline, result)
+        self.assertFormattingEqual(baseline, self._format(result))
+
+    def test_idempotency_check(self):
+        input, baseline = self._get_input_and_baseline('test1.zeek')
+
+        opts = zeekscript.FormatOptions(check_idempotency=True)
+        self.assertFormattingEqual(baseline, zeekscript.format_bytes(input, opts))
+
+    def test_idempotency_check_formatted(self):
+        # Checking an already formatted script takes a single pass.
+        _, baseline = self._get_input_and_baseline('test1.zeek')
+        format_to = zeekscript.Script._format_to
+
+        script = zeekscript.Script(io.BytesIO(baseline))
+        self.assertTrue(script.parse())
+
+        buf = io.BytesIO()
+        opts = zeekscript.FormatOptions(check_idempotency=True)
+        with unittest.mock.patch.object(zeekscript.Script, '_format_to', autospec=True,
+                                        side_effect=format_to) as mock:
+            script.format(buf, options=opts)
+        self.assertEqual(mock.call_count, 1)
+        self.assertFormattingEqual(baseline, buf.getvalue())
+
+    def test_format_cache(self):
+        # Formatting a cached result once more doesn't parse it again.
+        input, baseline = self._get_input_and_baseline('test1.zeek')
+        opts = zeekscript.FormatOptions(max_line_len=81)
+        cache = zeekscript.FormatCache()
+        result = cache.format_bytes(input, opts)
+        self.assertEqual(result, zeekscript.format_bytes(input, opts))
+
+        with unittest.mock.patch.object(zeekscript.Script, 'parse') as mock:
+            self.assertEqual(cache.format_bytes(result, opts), result)
+        mock.assert_not_called()
+
+        # Different options, other caches, the module-level functions, and
+        # idempotency checks do need the work.
+        opts_checked = zeekscript.FormatOptions(max_line_len=81, check_idempotency=True)
+        parse = zeekscript.Script.parse
+        for func in (lambda: cache.format_bytes(result),
+                     lambda: cache.format_bytes(result, opts_checked),
+                     lambda: zeekscript.FormatCache().format_bytes(result, opts),
+                     lambda: zeekscript.format_bytes(result, opts)):
+            with unittest.mock.patch.object(zeekscript.Script, 'parse', autospec=True,
+                                            side_effect=parse) as mock:
+                func()
+            mock.assert_called()
+
+    def test_idempotency_error(self):
+        nl = zeekscript.Formatter.NL
+        err = zeekscript.IdempotencyError(b'a' + nl + b'b' + nl, b'a' + nl + b'c' + nl)
+        self.assertEqual(err.diff().splitlines()[2:],
+                         ['@@ -1,2 +1,2 @@', ' a', '-b', '+c'])
+
+        err2 = pickle.loads(pickle.dumps(err))
+        self.assertEqual((err.pass1, err.pass2), (err2.pass1, err2.pass2))
+
+    def test_format_to_fixpoint(self):
+        input, baseline = self._get_input_and_baseline('test1.zeek')
+        self.assertFormattingEqual(baseline, zeekscript.format_to_fixpoint(input))
+
+        with self.assertRaises(ValueError):
+            zeekscript.format_to_fixpoint(input, max_iterations=1)
+
+    def test_format_to_fixpoint_unstable(self):
+        # A formatter that keeps adding to its output never stabilizes.
+        nl = zeekscript.Formatter.NL
+        fmt = zeekscript.Script.format
+
+        def format_and_append(script, output=None, enable_linebreaks=True, options=None):
+            fmt(script, output, enable_linebreaks, options)
+            output.write(b'#' + nl)
+
+        with unittest.mock.patch.object(zeekscript.Script, 'format', autospec=True,
+                                        side_effect=format_and_append):
+            with self.assertRaises(zeekscript.IdempotencyError) as ctx:
+                zeekscript.format_to_fixpoint(b'global x = 1;' + nl, max_iterations=3)
+
+        self.assertEqual(ctx.exception.iterations, 3)
+        self.assertEqual(ctx.exception.pass1.count(b'#'), 2)
+        self.assertEqual(ctx.exception.pass2.count(b'#'), 3)
+        self.assertEqual(str(ctx.exception), 'formatting did not stabilize after 3 passes')
+
+        err = pickle.loads(pickle.dumps(ctx.exception))
+        self.assertEqual(err.iterations, 3)
+
+    def test_parser_reuse(self):
+        input, baseline = self._get_input_and_baseline('test1.zeek')
+
+        # Consecutive scripts share the parser, and the sharing doesn't
+        # affect the result.
+        result1 = self._format(input)
+        ts_parser = zeekscript.Parser.TS_PARSER
+        result2 = self._format(input)
+
+        self.assertIs(ts_parser, zeekscript.Parser.TS_PARSER)
+        self.assertEqual(baseline, result1)
         self.assertEqual(baseline, result2)
 
+    def test_format_bytes(self):
+        input, baseline = self._get_input_and_baseline('test1.zeek')
+
+        self.assertEqual(baseline, zeekscript.format_bytes(input))
+        self.assertEqual(baseline, zeekscript.format_bytes(input.decode('UTF-8')))
+
+    def test_is_formatted(self):
+        input, baseline = self._get_input_and_baseline('test1.zeek')
+
+        self.assertFalse(zeekscript.is_formatted(input))
+        self.assertTrue(zeekscript.is_formatted(baseline))
+        self.assertTrue(zeekscript.is_formatted(baseline.decode('UTF-8')))
+
+    def test_is_formatted_empty(self):
+        self.assertTrue(zeekscript.is_formatted(b''))
+        self.assertTrue(zeekscript.is_formatted(''))
+        self.assertTrue(zeekscript.is_formatted(b'  ' + zeekscript.Formatter.NL))
+        self.assertTrue(zeekscript.is_formatted('\t\n\n'))
+
+    def test_is_formatted_trailing_newline(self):
+        _, baseline = self._get_input_and_baseline('test1.zeek')
+
+        # The formatter always terminates the last line, so a script lacking
+        # the final newline isn't formatted, nor is one with an extra one.
+        self.assertFalse(zeekscript.is_formatted(baseline.rstrip()))
+        self.assertFalse(zeekscript.is_formatted(baseline + zeekscript.Formatter.NL))
+
+    def test_format_diff(self):
+        input, baseline = self._get_input_and_baseline('test1.zeek')
+
+        self.assertIsNone(zeekscript.format_diff(baseline, 'test1.zeek'))
+
+        diff = zeekscript.format_diff(input, 'test1.zeek')
+        self.assertTrue(diff.startswith('--- a/test1.zeek\n+++ b/test1.zeek\n@@ '))
+
+    def test_format_diff_small_hunk(self):
+        nl = zeekscript.Formatter.NL
+        lines = [zeekscript.format_bytes('global g{} = {};'.format(n, n)) for n in range(500)]
+        lines[250] = 'global  g250=250;'.encode('UTF-8') + nl
+
+        diff = zeekscript.format_diff(b''.join(lines), 'many.zeek')
+        self.assertEqual(diff.count('\n@@ '), 1)
+        self.assertIn('-global  g250=250;', diff)
+        self.assertLess(len(diff.splitlines()), 15)
+
+    def test_format_stream(self):
+        input, baseline = self._get_input_and_baseline('test1.zeek')
+
+        buf = io.BytesIO()
+        zeekscript.format_stream(io.BytesIO(input), buf)
+        self.assertEqual(baseline, buf.getvalue())
+
+    def test_format_stream_text(self):
+        input, baseline = self._get_input_and_baseline('test1.zeek')
+
+        buf = io.StringIO(newline='')
+        zeekscript.format_stream(io.StringIO(input.decode('UTF-8'), newline=''), buf)
+        self.assertEqual(baseline.decode('UTF-8'), buf.getvalue())
+
+
+class OptionsTestCase(unittest.TestCase):
+    """Base class for tests of formatting options on short scripts.
+
+    Scripts are strings with Unix line endings. _format() returns the result
+    as bytes, formatted with idempotency checks and the options in OPTIONS,
+    which the keyword arguments extend or override.
+    """
+    OPTIONS = {}
+
+    def _bytes(self, content):
+        return content.encode('UTF-8').replace(b'\n', zeekscript.Formatter.NL)
+
+    def _text(self, content):
+        return content.replace(zeekscript.Formatter.NL, b'\n').decode('UTF-8')
+
+    def _format(self, content, **kwargs):
+        settings = dict(check_idempotency=True, **self.OPTIONS)
+        settings.update(kwargs)
+        return zeekscript.format_bytes(self._bytes(content), zeekscript.FormatOptions(**settings))
+
 
 class TestFormattingErrors(unittest.TestCase):
 
@@ -147,21 +344,37 @@ print "hi";
             'function foo() { if (\n',
             ('function foo( ) { if (', 0, 'cannot parse line 0, col 0: "function foo( ) { if ("'))
 
+    def test_passthrough(self):
+        opts = zeekscript.FormatOptions(passthrough_on_error=True)
+        for content in (b'event zeek_init() { foo)(); }', b'function foo( )  { if (',
+                        b'module Foo;\r\n\r\nfunction foo) { print  "hi" ; }\r\n'):
+            self.assertEqual(zeekscript.format_bytes(content, opts), content)
+
+        # Scripts without errors still get formatted:
+        self.assertEqual(zeekscript.format_bytes(b'global  a=1;\n', opts),
+                         self._to_bytes('global a = 1;\n'))
+
+        # Not tolerating the errors takes precedence:
+        opts.tolerate_parsing_errors = False
+        with self.assertRaises(zeekscript.ParserError):
+            zeekscript.format_bytes(b'event zeek_init() { foo)(); }', opts)
+
 
 class TestNewlineFormatting(unittest.TestCase):
     # This test verifies correct processing when line endings in the input
     # differ from that normally used by the platform.
 
-    def _get_formatted_and_baseline(self, filename):
+    def _foreign(self, data):
         # Swap line endings for something not native to the platform:
+        if zeekscript.Formatter.NL == b'\n':
+            # Turn everything to \r\n, even if mixed
+            data = data.replace(b'\r\n', b'\n')
+            return data.replace(b'\n', b'\r\n')
+        return data.replace(b'\r\n', b'\n')
+
+    def _get_formatted_and_baseline(self, filename, options=None):
         with open(os.path.join(DATA, filename), 'rb') as hdl:
-            data = hdl.read()
-            if zeekscript.Formatter.NL == b'\n':
-                # Turn everything to \r\n, even if mixed
-                data = data.replace(b'\r\n', b'\n')
-                data = data.replace(b'\n', b'\r\n')
-            else:
-                data = data.replace(b'\r\n', b'\n')
+            data = self._foreign(hdl.read())
 
         buf = io.BytesIO(data)
 
@@ -169,7 +382,7 @@ class TestNewlineFormatting(unittest.TestCase):
         script.parse()
 
         buf = io.BytesIO()
-        script.format(buf)
+        script.format(buf, options=options)
 
         with open(os.path.join(DATA, filename + '.out'), 'rb') as hdl:
             result_wanted = hdl.read()
@@ -178,52 +391,2017 @@ class TestNewlineFormatting(unittest.TestCase):
         return result_wanted, result_is
 
     def test_file_formatting(self):
+        # By default, the input's line endings carry over.
         result_wanted, result_is = self._get_formatted_and_baseline('test1.zeek')
+        self.assertEqual(self._foreign(result_wanted), result_is)
+
+    def test_file_formatting_native(self):
+        result_wanted, result_is = self._get_formatted_and_baseline(
+            'test1.zeek', zeekscript.FormatOptions(line_ending=zeekscript.LineEnding.NATIVE))
         self.assertEqual(result_wanted, result_is)
 
+    def test_crlf_roundtrip(self):
+        with open(os.path.join(DATA, 'test1.zeek.out'), 'rb') as hdl:
+            data = hdl.read().replace(b'\r\n', b'\n').replace(b'\n', b'\r\n')
 
-class TestScriptConstruction(unittest.TestCase):
-    DATA = 'event zeek_init() { }'
-    TMPFILE = 'tmp.zeek'
+        self.assertEqual(zeekscript.format_bytes(data), data)
+        self.assertTrue(zeekscript.is_formatted(data))
+
+        opts = zeekscript.FormatOptions(check_idempotency=True)
+        self.assertEqual(zeekscript.format_bytes(data, opts), data)
+
+    def test_explicit(self):
+        content = b'global  a=1;\r\nglobal  b=2;\r\n'
+        opts = zeekscript.FormatOptions(line_ending=zeekscript.LineEnding.LF)
+        self.assertEqual(zeekscript.format_bytes(content, opts), b'global a = 1;\nglobal b = 2;\n')
+
+        opts = zeekscript.FormatOptions(line_ending=zeekscript.LineEnding.CRLF)
+        self.assertEqual(zeekscript.format_bytes(content.replace(b'\r', b''), opts), content.replace(
+            b'  a=1', b' a = 1').replace(b'  b=2', b' b = 2'))
+
+    def test_mixed(self):
+        content = b'global a = 1;\r\nglobal b = 2;\nglobal c = 3;\r\n'
+        self.assertEqual(zeekscript.format_bytes(content),
+                         b'global a = 1;\r\nglobal b = 2;\r\nglobal c = 3;\r\n')
+
+
+class TestFormatOptions(unittest.TestCase):
+
+    def test_unknown_option(self):
+        with self.assertRaises(TypeError):
+            zeekscript.FormatOptions(no_such_option=True)
+
+    def test_no_linebreaks(self):
+        data = ('event zeek_init() { print "Lovely patio around the fountain.", '
+                '"Spent a lovely lunch on the patio."; }').encode('UTF-8')
+        script = zeekscript.Script(io.BytesIO(data))
+        self.assertTrue(script.parse())
+
+        buf = io.BytesIO()
+        script.format(buf, options=zeekscript.FormatOptions(enable_linebreaks=False))
+        self.assertEqual(len(buf.getvalue().split(zeekscript.Formatter.NL)), 5)
+
+    def test_intolerant_parsing(self):
+        script = zeekscript.Script(io.BytesIO(b'event zeek_init() { foo)(); }'))
+        self.assertFalse(script.parse())
+
+        opts = zeekscript.FormatOptions(tolerate_parsing_errors=False)
+        with self.assertRaises(zeekscript.ParserError) as ctx:
+            script.format(io.BytesIO(), options=opts)
+
+        self.assertEqual(ctx.exception.line, 0)
+        self.assertEqual(ctx.exception.column, 23)
+        self.assertEqual(ctx.exception.context, ')')
+        self.assertEqual(str(ctx.exception), 'cannot parse line 0, col 23: ")"')
+
+    def test_intolerant_parsing_first_error(self):
+        nl = zeekscript.Formatter.NL
+        script = zeekscript.Script(io.BytesIO(
+            b'module Foo;' + nl + b'function foo) { }' + nl + b'function bar) { }' + nl))
+        self.assertFalse(script.parse())
+
+        opts = zeekscript.FormatOptions(tolerate_parsing_errors=False)
+        with self.assertRaises(zeekscript.ParserError) as ctx:
+            script.format(io.BytesIO(), options=opts)
+
+        self.assertEqual(ctx.exception.line, 1)
+        self.assertEqual(ctx.exception.column, 0)
+        self.assertEqual(ctx.exception.context, 'function foo)')
+        self.assertEqual(ctx.exception.diagnostics, script.get_diagnostics())
+
+    def test_diagnostics(self):
+        script = zeekscript.Script(io.BytesIO(b'event zeek_init() { foo)(); }'))
+        self.assertFalse(script.parse())
+
+        self.assertEqual(script.get_diagnostics(), [
+            zeekscript.Diagnostic('cannot parse line 0, col 23: ")"', 23, 24, 0, 23, 'ERROR')])
+
+        script = zeekscript.Script(io.BytesIO(b'event zeek_init() { }'))
+        self.assertTrue(script.parse())
+        self.assertEqual(script.get_diagnostics(), [])
+
+    def test_format_with_diagnostics(self):
+        nl = zeekscript.Formatter.NL
+        content = b'module Foo;' + nl + b'function foo) { }' + nl + b'function bar) { }' + nl
+
+        result, diags = zeekscript.format_with_diagnostics(content)
+        self.assertIsNotNone(result)
+        self.assertGreaterEqual(len(diags), 2)
+        self.assertEqual(diags[0].start_row, 1)
+        self.assertEqual(diags[0].kind, 'ERROR')
+        self.assertGreater(diags[-1].start_row, diags[0].start_row)
+
+        opts = zeekscript.FormatOptions(tolerate_parsing_errors=False)
+        result, diags2 = zeekscript.format_with_diagnostics(content, opts)
+        self.assertIsNone(result)
+        self.assertEqual(diags, diags2)
+
+        result, diags = zeekscript.format_with_diagnostics(b'event zeek_init() { }')
+        self.assertEqual(diags, [])
+
+    def test_intolerant_parsing_empty(self):
+        script = zeekscript.Script(io.BytesIO(b''))
+        self.assertTrue(script.parse())
+
+        buf = io.BytesIO()
+        script.format(buf, options=zeekscript.FormatOptions(tolerate_parsing_errors=False))
+        self.assertEqual(buf.getvalue(), b'')
+
+    def test_from_toml(self):
+        opts = zeekscript.FormatOptions.from_toml(
+            'max_line_len = 100\nindent = 4\nline_ending = "crlf"\nsort_loads = true\n')
+        self.assertEqual(opts.max_line_len, 100)
+        self.assertEqual(opts.indent, 4)
+        self.assertEqual(opts.line_ending, zeekscript.LineEnding.CRLF)
+        self.assertTrue(opts.sort_loads)
+        self.assertEqual(opts.tab_size, 8)
+
+        opts = zeekscript.FormatOptions.from_toml(b'indent = "  "')
+        self.assertEqual(opts.indent, '  ')
+
+        opts = zeekscript.FormatOptions.from_toml('max_line_len = 1\nmax_blank_lines = 0')
+        self.assertEqual((opts.max_line_len, opts.max_blank_lines), (1, 0))
+
+    def test_to_toml(self):
+        opts = zeekscript.FormatOptions(max_line_len=100, indent=4, sort_loads=True,
+                                        line_ending=zeekscript.LineEnding.CRLF,
+                                        brace_style=zeekscript.BraceStyle.ALLMAN)
+        data = opts.to_dict()
+        self.assertEqual((data['max_line_len'], data['indent'], data['sort_loads']),
+                         (100, 4, True))
+        self.assertEqual((data['line_ending'], data['brace_style']), ('crlf', 'allman'))
+        self.assertEqual(list(data), list(vars(zeekscript.FormatOptions())))
+
+        self.assertIn('line_ending = "crlf"\n', opts.to_toml())
+
+        # Rendered as TOML, options read back as they were.
+        for opts in (zeekscript.FormatOptions(), opts,
+                     zeekscript.FormatOptions(indent=' \t"\\ü', final_newline=False)):
+            self.assertEqual(vars(zeekscript.FormatOptions.from_toml(opts.to_toml())),
+                             vars(opts))
+
+    def test_from_toml_errors(self):
+        for content, msg in (
+                ('max_line_length = 100', 'unknown formatting option "max_line_length"'),
+                ('max_line_len = "100"', 'option "max_line_len" requires an integer value'),
+                ('sort_loads = 1', 'option "sort_loads" requires a boolean value'),
+                ('max_line_len = true', 'option "max_line_len" requires an integer value'),
+                ('indent = 4.0', 'option "indent" requires a string or an integer'),
+                ('max_line_len = 0', 'option "max_line_len" requires a positive integer'),
+                ('max_line_len = -5', 'option "max_line_len" requires a positive integer'),
+                ('tab_size = 0', 'option "tab_size" requires a positive integer'),
+                ('max_blank_lines = -1',
+                 'option "max_blank_lines" requires a non-negative integer'),
+                ('line_ending = "cr"', 'invalid line ending "cr", expected one of '
+                 '"auto", "lf", "crlf", "native"')):
+            with self.assertRaises(zeekscript.ConfigError) as ctx:
+                zeekscript.FormatOptions.from_toml(content)
+            self.assertEqual(str(ctx.exception), msg)
+
+        with self.assertRaises(zeekscript.ConfigError):
+            zeekscript.FormatOptions.from_toml('max_line_len = ')
+
+    def test_find_config_file(self):
+        tmpdir = os.path.realpath(tempfile.mkdtemp())
 
-    def test_file(self):
-        # tempfile.NamedTemporaryFile doesn't seem to support reading while
-        # still existing on some platforms, so going manual here:
         try:
-            with open(self.TMPFILE, 'w') as hdl:
-                hdl.write(self.DATA)
-            script = zeekscript.Script(self.TMPFILE)
-            script.parse()
+            os.makedirs(os.path.join(tmpdir, 'a', 'b'))
+            for dirname in (tmpdir, os.path.join(tmpdir, 'a')):
+                with open(os.path.join(dirname, '.zeek-format.toml'), 'w') as hdl:
+                    hdl.write('max_line_len = {}\n'.format(len(dirname)))
+
+            # The closest configuration file wins:
+            config = zeekscript.find_config_file(os.path.join(tmpdir, 'a', 'b', 'foo.zeek'))
+            self.assertEqual(config, os.path.join(tmpdir, 'a', '.zeek-format.toml'))
+            self.assertEqual(zeekscript.FormatOptions.from_file(config).max_line_len,
+                             len(os.path.join(tmpdir, 'a')))
+
+            config = zeekscript.find_config_file(os.path.join(tmpdir, 'foo.zeek'))
+            self.assertEqual(config, os.path.join(tmpdir, '.zeek-format.toml'))
+            self.assertEqual(zeekscript.find_config_file(tmpdir), config)
         finally:
-            os.unlink(self.TMPFILE)
+            shutil.rmtree(tmpdir)
+
+    def test_from_editorconfig(self):
+        tmpdir = os.path.realpath(tempfile.mkdtemp())
 
-    def test_path(self):
         try:
-            with open(self.TMPFILE, 'w') as hdl:
-                hdl.write(self.DATA)
-            script = zeekscript.Script(pathlib.Path(hdl.name))
-            script.parse()
+            os.makedirs(os.path.join(tmpdir, 'a', 'b'))
+            with open(os.path.join(tmpdir, '.editorconfig'), 'w') as hdl:
+                hdl.write('root = true\n\n[*]\nindent_style = space\nindent_size = 2\n'
+                          '\n[*.zeek]\nend_of_line = CRLF\ninsert_final_newline = false\n'
+                          '\n[*.py]\nindent_size = 4\n')
+            with open(os.path.join(tmpdir, 'a', '.editorconfig'), 'w') as hdl:
+                hdl.write('# Nested\n[*.{zeek,bro}]\nindent_style = tab\ntab_width = 4\n'
+                          '[b/**]\nend_of_line = unset\n')
+
+            opts = zeekscript.FormatOptions.from_editorconfig(os.path.join(tmpdir, 'foo.zeek'))
+            self.assertEqual(opts.indent, 2)
+            self.assertEqual(opts.tab_size, 2)
+            self.assertEqual(opts.line_ending, zeekscript.LineEnding.CRLF)
+            self.assertFalse(opts.final_newline)
+
+            # The nested file overrides the root one:
+            opts = zeekscript.FormatOptions.from_editorconfig(os.path.join(tmpdir, 'a', 'foo.zeek'))
+            self.assertEqual(opts.indent, '\t')
+            self.assertEqual(opts.tab_size, 4)
+            self.assertEqual(opts.line_ending, zeekscript.LineEnding.CRLF)
+
+            opts = zeekscript.FormatOptions.from_editorconfig(os.path.join(tmpdir, 'a', 'b', 'foo.zeek'))
+            self.assertEqual(opts.line_ending, zeekscript.LineEnding.AUTO)
+            self.assertFalse(opts.final_newline)
+
+            # Configuration files build on it:
+            toml = os.path.join(tmpdir, 'zeekscript.toml')
+            with open(toml, 'w') as hdl:
+                hdl.write('indent = 4\n')
+
+            opts = zeekscript.FormatOptions.from_file(toml, base=opts)
+            self.assertEqual((opts.indent, opts.tab_size, opts.final_newline), (4, 4, False))
         finally:
-            os.unlink(self.TMPFILE)
+            shutil.rmtree(tmpdir)
+
+    def test_from_editorconfig_ranges(self):
+        tmpdir = os.path.realpath(tempfile.mkdtemp())
 
-    def test_stdin(self):
         try:
-            oldstdin = sys.stdin
-            sys.stdin = io.StringIO(self.DATA)
-            script = zeekscript.Script('-')
-            script.parse()
+            # Huge ranges cost no more than small ones.
+            with open(os.path.join(tmpdir, '.editorconfig'), 'w') as hdl:
+                hdl.write('root = true\n[file{0..99999999}.zeek]\nindent_style = space\n'
+                          'indent_size = 2\n[v{5..-5}.zeek]\nend_of_line = crlf\n')
+
+            for fname, matches in (('file0.zeek', True), ('file99999999.zeek', True),
+                                   ('file100000000.zeek', False), ('file-1.zeek', False),
+                                   ('filex.zeek', False)):
+                opts = zeekscript.FormatOptions.from_editorconfig(os.path.join(tmpdir, fname))
+                self.assertEqual(opts.indent == 2, matches, fname)
+
+            for fname, matches in (('v-5.zeek', True), ('v3.zeek', True), ('v6.zeek', False)):
+                opts = zeekscript.FormatOptions.from_editorconfig(os.path.join(tmpdir, fname))
+                self.assertEqual(opts.line_ending == zeekscript.LineEnding.CRLF, matches, fname)
         finally:
-            sys.stdin = oldstdin
+            shutil.rmtree(tmpdir)
 
-    def test_text_fileobj(self):
-        obj = io.StringIO(self.DATA)
-        script = zeekscript.Script(obj)
-        script.parse()
+    def test_discover(self):
+        tmpdir = os.path.realpath(tempfile.mkdtemp())
 
-    def test_bytes_fileobj(self):
-        obj = io.BytesIO(self.DATA.encode('UTF-8'))
-        script = zeekscript.Script(obj)
-        script.parse()
+        try:
+            os.makedirs(os.path.join(tmpdir, 'a', 'b'))
+            with open(os.path.join(tmpdir, 'zeekscript.toml'), 'w') as hdl:
+                hdl.write('max_line_len = 100\ntab_size = 4\n')
+
+            opts = zeekscript.FormatOptions.discover(os.path.join(tmpdir, 'a', 'b'))
+            self.assertEqual((opts.max_line_len, opts.tab_size), (100, 4))
+
+            opts = zeekscript.FormatOptions.from_file(pathlib.Path(tmpdir, 'zeekscript.toml'))
+            self.assertEqual((opts.max_line_len, opts.tab_size), (100, 4))
+
+            # In the same directory, .zeek-format.toml takes precedence:
+            with open(os.path.join(tmpdir, '.zeek-format.toml'), 'w') as hdl:
+                hdl.write('max_line_len = 120\n')
+
+            opts = zeekscript.FormatOptions.discover(os.path.join(tmpdir, 'a', 'foo.zeek'))
+            self.assertEqual((opts.max_line_len, opts.tab_size), (120, 8))
+
+            with self.assertRaises(zeekscript.ConfigError):
+                zeekscript.FormatOptions.from_file(os.path.join(tmpdir, 'nosuchfile.toml'))
+        finally:
+            shutil.rmtree(tmpdir)
+
+
+class TestIndentation(unittest.TestCase):
+    INPUT = b'event zeek_init() { if ( T ) { print 1; } }'
+
+    def _format(self, content, options):
+        script = zeekscript.Script(io.BytesIO(content))
+        self.assertTrue(script.parse())
+
+        buf = io.BytesIO()
+        script.format(buf, options=options)
+
+        return buf.getvalue()
+
+    def _to_bytes(self, content):
+        return content.encode('UTF-8').replace(b'\n', zeekscript.Formatter.NL)
+
+    def test_tabs(self):
+        result = self._format(self.INPUT, zeekscript.FormatOptions())
+        self.assertEqual(result, self._to_bytes("""event zeek_init()
+	{
+	if ( T )
+		{
+		print 1;
+		}
+	}
+"""))
+
+    def test_spaces(self):
+        result = self._format(self.INPUT, zeekscript.FormatOptions(indent='    '))
+        self.assertEqual(result, self._to_bytes("""event zeek_init()
+    {
+    if ( T )
+        {
+        print 1;
+        }
+    }
+"""))
+
+    def test_spaces_count(self):
+        self.assertEqual(self._format(self.INPUT, zeekscript.FormatOptions(indent=4)),
+                         self._format(self.INPUT, zeekscript.FormatOptions(indent='    ')))
+
+    def test_invalid_indent(self):
+        for indent in ('', 0, '--'):
+            with self.assertRaises(ValueError):
+                self._format(self.INPUT, zeekscript.FormatOptions(indent=indent))
+
+    def test_wrapped_spaces(self):
+        # Continuation lines add their alignment onto the indentation:
+        str1, str2 = '"' + 'a' * 40 + '"', '"' + 'b' * 41 + '"'
+        content = ('event zeek_init() { if ( T ) { print ' + str1 + ', ' +
+                   str2 + '; } }').encode('UTF-8')
+        result = self._format(content, zeekscript.FormatOptions(indent='  '))
+        self.assertEqual(result, self._to_bytes("""event zeek_init()
+  {
+  if ( T )
+    {
+    print """ + str1 + """,
+        """ + str2 + """;
+    }
+  }
+"""))
+
+
+class TestLineLength(unittest.TestCase):
+    STR1, STR2 = '"' + 'a' * 40 + '"', '"' + 'b' * 41 + '"'
+    INPUT = ('event zeek_init() { print ' + STR1 + ', ' + STR2 + '; }').encode('UTF-8')
+
+    def _format(self, options):
+        script = zeekscript.Script(io.BytesIO(self.INPUT))
+        self.assertTrue(script.parse())
+
+        buf = io.BytesIO()
+        script.format(buf, options=options)
+
+        return buf.getvalue()
+
+    def _to_bytes(self, content):
+        return content.encode('UTF-8').replace(b'\n', zeekscript.Formatter.NL)
+
+    def test_default_width(self):
+        self.assertEqual(self._format(zeekscript.FormatOptions()), self._to_bytes(
+            'event zeek_init()\n\t{\n\tprint ' + self.STR1 + ',\n\t    ' +
+            self.STR2 + ';\n\t}\n'))
+
+    def test_wide(self):
+        self.assertEqual(self._format(zeekscript.FormatOptions(max_line_len=120)), self._to_bytes(
+            'event zeek_init()\n\t{\n\tprint ' + self.STR1 + ', ' +
+            self.STR2 + ';\n\t}\n'))
+
+    def test_initializer(self):
+        # A 106-character initializer stays intact only with a wider limit.
+        content = ('global codes: vector of string = { ' +
+                   ', '.join(['"' + c * 10 + '"' for c in 'abcde']) + ' };\n')
+        script = zeekscript.Script(io.BytesIO(content.encode('UTF-8')))
+        self.assertTrue(script.parse())
+
+        buf = io.BytesIO()
+        script.format(buf, options=zeekscript.FormatOptions(max_line_len=120))
+        self.assertEqual(buf.getvalue(), self._to_bytes(content))
+
+        buf = io.BytesIO()
+        script.format(buf)
+        self.assertGreater(len(buf.getvalue().splitlines()), 1)
+
+
+class TestFormatFile(unittest.TestCase):
+
+    def setUp(self):
+        self.tmpdir = tempfile.mkdtemp()
+        self.fname = os.path.join(self.tmpdir, 'test1.zeek')
+        shutil.copy(os.path.join(DATA, 'test1.zeek'), self.fname)
+
+        with open(os.path.join(DATA, 'test1.zeek.out'), 'rb') as hdl:
+            self.baseline = hdl.read()
+
+    def tearDown(self):
+        shutil.rmtree(self.tmpdir)
+
+    def _read(self):
+        with open(self.fname, 'rb') as hdl:
+            return hdl.read()
+
+    def test_not_inplace(self):
+        with open(os.path.join(DATA, 'test1.zeek'), 'rb') as hdl:
+            input = hdl.read()
+
+        self.assertEqual(zeekscript.format_file(self.fname), self.baseline)
+        self.assertEqual(zeekscript.format_file(pathlib.Path(self.fname)), self.baseline)
+        self.assertEqual(self._read(), input)
+
+    def test_inplace(self):
+        self.assertEqual(zeekscript.format_file(self.fname, inplace=True),
+                         zeekscript.FileOutcome.REWRITTEN)
+        self.assertEqual(self._read(), self.baseline)
+
+        # Only the formatted script remains, no temporary files:
+        self.assertEqual(os.listdir(self.tmpdir), ['test1.zeek'])
+
+    def test_inplace_unchanged(self):
+        with open(self.fname, 'wb') as hdl:
+            hdl.write(self.baseline)
+        mtime = os.stat(self.fname).st_mtime_ns - 1000000000
+        os.utime(self.fname, ns=(mtime, mtime))
+
+        self.assertEqual(zeekscript.format_file(self.fname, inplace=True),
+                         zeekscript.FileOutcome.UNCHANGED)
+        self.assertEqual(os.stat(self.fname).st_mtime_ns, mtime)
+
+    def test_inplace_symlink_outcome(self):
+        link = os.path.join(self.tmpdir, 'link.zeek')
+        try:
+            os.symlink(self.fname, link)
+        except (OSError, NotImplementedError):
+            self.skipTest('symlinks not supported')
+
+        # The outcomes reflect the link's target.
+        self.assertEqual(zeekscript.format_file(link, inplace=True),
+                         zeekscript.FileOutcome.REWRITTEN)
+        self.assertEqual(self._read(), self.baseline)
+        self.assertEqual(zeekscript.format_file(link, inplace=True),
+                         zeekscript.FileOutcome.UNCHANGED)
+        self.assertTrue(os.path.islink(link))
+
+    def test_in_place(self):
+        self.assertTrue(zeekscript.format_file_in_place(self.fname))
+        self.assertEqual(self._read(), self.baseline)
+        self.assertFalse(zeekscript.format_file_in_place(self.fname))
+
+    @unittest.skipIf(os.name != 'posix', 'requires POSIX permissions')
+    def test_inplace_permissions(self):
+        os.chmod(self.fname, 0o640)
+        zeekscript.format_file(self.fname, inplace=True)
+        self.assertEqual(os.stat(self.fname).st_mode & 0o777, 0o640)
+
+    def test_inplace_symlink(self):
+        # Formatting via a symlink rewrites its target and keeps the link.
+        link = os.path.join(self.tmpdir, 'link.zeek')
+        try:
+            os.symlink('test1.zeek', link)
+        except (OSError, NotImplementedError):
+            self.skipTest('symlinks not supported')
+
+        zeekscript.format_file(link, inplace=True)
+        self.assertTrue(os.path.islink(link))
+        self.assertEqual(self._read(), self.baseline)
+        self.assertEqual(sorted(os.listdir(self.tmpdir)), ['link.zeek', 'test1.zeek'])
+
+    def test_directory(self):
+        with self.assertRaises(zeekscript.FileError):
+            zeekscript.format_file(self.tmpdir)
+        with self.assertRaises(zeekscript.FileError):
+            zeekscript.format_file(self.tmpdir, inplace=True)
+
+    def test_missing_file(self):
+        with self.assertRaises(zeekscript.FileError):
+            zeekscript.format_file(os.path.join(self.tmpdir, 'nosuchfile.zeek'))
+
+
+class TestFormatDir(unittest.TestCase):
+
+    def setUp(self):
+        self.tmpdir = tempfile.mkdtemp()
+        os.makedirs(os.path.join(self.tmpdir, 'b', 'c'))
+
+        for name in ('z.zeek', os.path.join('b', 'c', 'y.zeek'), os.path.join('b', 'x.txt')):
+            shutil.copy(os.path.join(DATA, 'test1.zeek'), os.path.join(self.tmpdir, name))
+
+        shutil.copy(os.path.join(DATA, 'test1.zeek.out'), os.path.join(self.tmpdir, 'a.zeek'))
+
+        with open(os.path.join(self.tmpdir, 'b', 'broken.zeek'), 'w') as hdl:
+            hdl.write('event zeek_init() { foo)(); }\n')
+
+        with open(os.path.join(DATA, 'test1.zeek.out'), 'rb') as hdl:
+            self.baseline = hdl.read()
+
+    def tearDown(self):
+        shutil.rmtree(self.tmpdir)
+
+    def _path(self, *parts):
+        return os.path.join(self.tmpdir, *parts)
+
+    def _check(self, results):
+        self.assertEqual([res.path for res in results], [
+            self._path('a.zeek'), self._path('b', 'broken.zeek'),
+            self._path('b', 'c', 'y.zeek'), self._path('z.zeek')])
+        self.assertEqual([res.changed for res in results], [False, False, True, True])
+
+        self.assertIsNone(results[0].error)
+        self.assertIsInstance(results[1].error, zeekscript.ParserError)
+        self.assertIsNone(results[2].error)
+
+        for name in (('z.zeek',), ('b', 'c', 'y.zeek')):
+            with open(self._path(*name), 'rb') as hdl:
+                self.assertEqual(hdl.read(), self.baseline)
+
+    def test_sequential(self):
+        opts = zeekscript.FormatOptions(tolerate_parsing_errors=False)
+        self._check(zeekscript.format_dir(self.tmpdir, opts, jobs=1))
+
+    def test_parallel(self):
+        opts = zeekscript.FormatOptions(tolerate_parsing_errors=False)
+        self._check(zeekscript.format_dir(pathlib.Path(self.tmpdir), opts, jobs=3))
+
+    def test_serial_default(self):
+        # Without explicit jobs, no worker processes get involved.
+        opts = zeekscript.FormatOptions(tolerate_parsing_errors=False)
+        with unittest.mock.patch('concurrent.futures.ProcessPoolExecutor') as mock:
+            self._check(zeekscript.format_dir(self.tmpdir, opts))
+        mock.assert_not_called()
+
+    def test_not_a_directory(self):
+        with self.assertRaises(zeekscript.FileError):
+            zeekscript.format_dir(self._path('z.zeek'))
+
+
+class TestFormatMany(unittest.TestCase):
+
+    def setUp(self):
+        with open(os.path.join(DATA, 'test1.zeek'), 'rb') as hdl:
+            self.input = hdl.read()
+        with open(os.path.join(DATA, 'test1.zeek.out'), 'rb') as hdl:
+            self.baseline = hdl.read()
+
+        self.broken = b'event zeek_init() { foo)(); }' + zeekscript.Formatter.NL
+        self.options = zeekscript.FormatOptions(tolerate_parsing_errors=False)
+
+    def _check(self, results):
+        self.assertEqual(len(results), 5)
+
+        for idx, res in enumerate(results):
+            if idx == 2:
+                self.assertIsInstance(res, zeekscript.ParserError)
+            else:
+                self.assertEqual(res, self.baseline)
+
+    def test_sequential(self):
+        sources = [self.input, self.baseline, self.broken, self.input.decode('UTF-8'), self.input]
+        self._check(zeekscript.format_many(sources, self.options, jobs=1))
+
+    def test_parallel(self):
+        sources = [self.input, self.baseline, self.broken, self.input.decode('UTF-8'), self.input]
+        self._check(zeekscript.format_many(iter(sources), self.options, jobs=3))
+
+    def test_directory(self):
+        tmpdir = tempfile.mkdtemp()
+
+        try:
+            for name in ('a.zeek', 'b.zeek', 'd.zeek', 'e.zeek'):
+                shutil.copy(os.path.join(DATA, 'test1.zeek'), os.path.join(tmpdir, name))
+            with open(os.path.join(tmpdir, 'c.zeek'), 'wb') as hdl:
+                hdl.write(self.broken)
+
+            sources = []
+            for path in zeekscript.collect_zeek_files(tmpdir):
+                with open(path, 'rb') as hdl:
+                    sources.append(hdl.read())
+
+            with unittest.mock.patch('concurrent.futures.ProcessPoolExecutor') as mock:
+                self._check(zeekscript.format_many(sources, self.options))
+            mock.assert_not_called()
+        finally:
+            shutil.rmtree(tmpdir)
+
+
+class TestCollapseShortFunctions(OptionsTestCase):
+    ONELINER = 'global f = function(i: count): string { return fmt("%d", i); };'
+
+    def test_default_expands(self):
+        result = self._format(self.ONELINER)
+        self.assertGreater(len(result.splitlines()), 1)
+
+    def test_collapse(self):
+        result = self._format(self.ONELINER, collapse_short_functions=True)
+        self.assertEqual(result, self._bytes(self.ONELINER + '\n'))
+
+        # The collapsed form is stable:
+        self.assertEqual(result, self._format(self._text(result), collapse_short_functions=True))
+
+    def test_expanded_input_collapses(self):
+        expanded = self._text(self._format(self.ONELINER))
+        self.assertEqual(self._format(expanded, collapse_short_functions=True),
+                         self._format(self.ONELINER, collapse_short_functions=True))
+
+    def test_too_long(self):
+        content = self.ONELINER.replace('"%d"', '"' + 'x' * 60 + '%d"')
+        result = self._format(content, collapse_short_functions=True)
+        self.assertGreater(len(result.splitlines()), 1)
+
+    def test_multiple_statements(self):
+        content = 'global f = function(i: count) { print i; print i; };'
+        result = self._format(content, collapse_short_functions=True)
+        self.assertGreater(len(result.splitlines()), 1)
+
+    def test_comment(self):
+        content = 'global f = function(i: count) {\n# Say it.\nprint i;\n};'
+        result = self._format(content, collapse_short_functions=True)
+        self.assertIn(b'# Say it.', result)
+        self.assertGreater(len(result.splitlines()), 1)
+
+    def test_call_argument(self):
+        content = 'event zeek_init()\n\t{\n\tv = sort(v, function(a: int, b: int): int { return a - b; });\n\t}\n'
+        result = self._format(content, collapse_short_functions=True)
+        self.assertEqual(result, self._bytes(content))
+        self.assertGreater(len(self._format(content).splitlines()), 4)
+
+    def test_attribute(self):
+        content = 'global t: table[count] of int &default=function(i: count): int { return 0; };\n'
+        result = self._format(content, collapse_short_functions=True)
+        self.assertEqual(result, self._bytes(content))
+
+
+class TestAlignTableValues(OptionsTestCase):
+    INPUT = ('const t: table[count] of string = { [1] = "one", [100] = "hundred", '
+             '[2, 3] = "two-three" };')
+
+    def _format(self, content, **kwargs):
+        return self._text(super()._format(content, **kwargs))
+
+    def test_default(self):
+        self.assertIn('\t[1] = "one",\n', self._format(self.INPUT))
+
+    def test_align(self):
+        result = self._format(self.INPUT, align_table_values=True)
+        self.assertEqual(result, """const t: table[count] of string = {
+	[1]    = "one",
+	[100]  = "hundred",
+	[2, 3] = "two-three"
+};
+""")
+        # Alignment is stable:
+        self.assertEqual(result, self._format(result, align_table_values=True))
+
+    def test_blank_line_resets(self):
+        content = self.INPUT.replace('"hundred", ', '"hundred",\n\n')
+        result = self._format(content, align_table_values=True)
+        self.assertIn('\t[1]   = "one",\n\t[100] = "hundred",\n\n\t[2, 3] = "two-three"\n',
+                      result)
+
+    def test_display_width(self):
+        # Keys line up on screen, whatever their encoded length.
+        content = 'const t: table[string] of count = { ["ü"] = 1, ["日本"] = 2, ["abcd"] = 3 };'
+        self.assertEqual(self._format(content, align_table_values=True), """const t: table[string] of count = {
+	["ü"]    = 1,
+	["日本"] = 2,
+	["abcd"] = 3
+};
+""")
+
+    def test_record_constructor(self):
+        # Record fields keep their layout.
+        content = ('global r: R = [$a=1, $bbbbbbbbbbbbbbbb=2, $cccccccccccccccccccccccc=3, '
+                   '$dddddddddddddddddddddddd=4, $eeeeeeeeeeeeeeeeeeeeeeee=5];')
+        result = self._format(content, align_table_values=True)
+        self.assertGreater(len(result.splitlines()), 1)
+        self.assertEqual(result, self._format(content))
+
+
+class TestAlignFieldComments(OptionsTestCase):
+    INPUT = """type R: record {
+	a: count; ##< A field
+	longer_name: string &optional; ##< Another field
+	##< continued
+	c: bool;
+
+	d: count; ##< After a blank line
+	eeeee: count; ##< Again
+};
+
+type E: enum {
+	A, ##< A value
+	BBBB, ##< Another value
+};
+"""
+
+    def test_default(self):
+        self.assertIn(self._bytes('\ta: count; ##< A field\n'), self._format(self.INPUT))
+
+    def test_align(self):
+        self.assertEqual(self._format(self.INPUT, align_field_comments=True), self._bytes(
+            """type R: record {
+	a: count;                      ##< A field
+	longer_name: string &optional; ##< Another field
+	                               ##< continued
+	c: bool;
+
+	d: count;     ##< After a blank line
+	eeeee: count; ##< Again
+};
+
+type E: enum {
+	A,    ##< A value
+	BBBB, ##< Another value
+};
+"""))
+
+    def test_long_line(self):
+        # A line reaching the maximum line length doesn't drag the others along.
+        content = 'type R: record {\n\ta: count; ##< A\n\tbb: count; ##< B\n};\n'
+        self.assertEqual(self._format(content, align_field_comments=True, max_line_len=18),
+                         self._bytes('type R: record {\n\ta: count; ##< A\n\tbb: count; ##< B\n};\n'))
+
+
+class TestSortLoads(OptionsTestCase):
+    INPUT = """@load base/utils/site
+@load ./main # Local stuff
+@load base/frameworks/notice
+
+# Policy
+@load policy/tuning/defaults
+@load base/protocols/conn
+"""
+
+    def _format(self, content, **kwargs):
+        return self._text(super()._format(content, **kwargs))
+
+    def test_default(self):
+        self.assertEqual(self._format(self.INPUT), self.INPUT)
+
+    def test_sort(self):
+        result = self._format(self.INPUT, sort_loads=True)
+        self.assertEqual(result, """@load ./main # Local stuff
+@load base/frameworks/notice
+@load base/utils/site
+
+# Policy
+@load base/protocols/conn
+@load policy/tuning/defaults
+""")
+        self.assertEqual(result, self._format(result, sort_loads=True))
+
+    def test_other_directives(self):
+        # Each kind of directive sorts separately, in place.
+        content = """@load-plugin Zeek::Foo
+@load-plugin Corelight::Bar
+@load-sigs ./sigs
+@load-sigs ./more-sigs
+@load b
+@load a
+"""
+        self.assertEqual(self._format(content, sort_loads=True), """@load-plugin Corelight::Bar
+@load-plugin Zeek::Foo
+@load-sigs ./more-sigs
+@load-sigs ./sigs
+@load a
+@load b
+""")
+
+    def test_duplicates(self):
+        # Identical lines in a run collapse, ones in separate runs remain.
+        content = """@load b
+@load a
+@load b
+
+@load a
+@load a # Again
+"""
+        self.assertEqual(self._format(content, sort_loads=True), """@load a
+@load b
+
+@load a
+@load a # Again
+""")
+
+
+class TestSortAttributes(OptionsTestCase):
+    INPUT = """type Info: record {
+	a: count &default=0 &optional &log;
+	b: string &redef &deprecated="Use a." &log;
+};
+
+global c: table[count] of string &redef &create_expire=1 min &default="none";
+"""
+
+    def test_default(self):
+        self.assertEqual(self._format(self.INPUT), self._bytes(self.INPUT))
+
+    def test_sort(self):
+        self.assertEqual(self._format(self.INPUT, sort_attributes=True), self._bytes(
+            """type Info: record {
+	a: count &log &optional &default=0;
+	b: string &log &deprecated="Use a." &redef;
+};
+
+global c: table[count] of string &default="none" &create_expire=1 min &redef;
+"""))
+
+    def test_multiline(self):
+        # Line breaks between attributes would move along, so these stay put.
+        content = """global c: table[count] of string &redef
+
+	&default="none";
+"""
+        self.assertEqual(self._format(content, sort_attributes=True), self._format(content))
+
+
+class TestReflowDocComments(OptionsTestCase):
+
+    OPTIONS = {'reflow_doc_comments': True, 'max_line_len': 40}
+
+    def test_reflow(self):
+        content = """##! A module comment that is long enough to need wrapping.
+
+## Short
+## lines get
+## joined.
+##
+## A second paragraph that is long enough to need wrapping, too.
+global a = 1;
+"""
+        expected = """##! A module comment that is long enough
+##! to need wrapping.
+
+## Short lines get joined.
+##
+## A second paragraph that is long
+## enough to need wrapping, too.
+global a = 1;
+"""
+        self.assertEqual(self._format(content), self._bytes(expected))
+
+        # The flag is off by default.
+        self.assertEqual(zeekscript.format_bytes(self._bytes(content)), self._bytes(content))
+
+    def test_markup(self):
+        # Markup, indented blocks, and ##< comments stay as they are, and
+        # parameter descriptions start paragraphs of their own.
+        content = """## Generated for
+## events.
+## c: The connection.
+## is_orig: Whether the originator did.
+##
+## .. zeek:see:: some_other_event yet_another_event
+## - A list item that is much too long for the line.
+##     indented code that is much too long for the line
+global a = 1; ##< A comment that is much too long for the line.
+"""
+        expected = content.replace('## Generated for\n## events.', '## Generated for events.')
+        self.assertEqual(self._format(content), self._bytes(expected))
+
+    def test_indented(self):
+        content = 'export {\n\t## A comment with words to wrap at the limit.\n\tglobal a = 1;\n}\n'
+        expected = 'export {\n\t## A comment with words to wrap\n\t## at the limit.\n\tglobal a = 1;\n}\n'
+        self.assertEqual(self._format(content), self._bytes(expected))
+
+
+class TestMaxBlankLines(OptionsTestCase):
+
+    def _toplevel(self, blank_lines):
+        return 'global a = 1;\n' + '\n' * blank_lines + 'global b = 2;\n'
+
+    def _body(self, blank_lines):
+        return ('event zeek_init()\n\t{\n\tprint 1;\n' + '\n' * blank_lines +
+                '\tprint 2;\n\t}\n')
+
+    def test_default(self):
+        for script in (self._toplevel, self._body):
+            self.assertEqual(self._format(script(1)), self._bytes(script(1)))
+            self.assertEqual(self._format(script(2)), self._bytes(script(1)))
+            self.assertEqual(self._format(script(5)), self._bytes(script(1)))
+
+    def test_two(self):
+        for script in (self._toplevel, self._body):
+            self.assertEqual(self._format(script(1), max_blank_lines=2), self._bytes(script(1)))
+            self.assertEqual(self._format(script(2), max_blank_lines=2), self._bytes(script(2)))
+            self.assertEqual(self._format(script(5), max_blank_lines=2), self._bytes(script(2)))
+
+    def test_zero(self):
+        # A blank line the author put in always survives.
+        self.assertEqual(self._format(self._toplevel(5), max_blank_lines=0),
+                         self._bytes(self._toplevel(1)))
+
+    def test_module_summary(self):
+        # Blank lines keep the summary detached from what follows.
+        content = '##! A summary.\n##!\n##! More detail.\n{}module Foo;\n\n{}global a = 1;\n'
+        self.assertEqual(self._format(content.format('\n' * 4, '\n' * 3)),
+                         self._bytes(content.format('\n', '')))
+        self.assertEqual(self._format(content.format('\n' * 4, '\n' * 3), max_blank_lines=2),
+                         self._bytes(content.format('\n\n', '\n')))
+
+    def test_comments(self):
+        content = '# One\n#\n# Two\n\n\n\nglobal a = 1;\n'
+        self.assertEqual(self._format(content, max_blank_lines=2),
+                         self._bytes(content.replace('\n\n\n\n', '\n\n\n')))
+
+
+class TestExpandLongConstructors(OptionsTestCase):
+
+    OPTIONS = {'expand_long_constructors': True}
+
+    def test_short(self):
+        for content in ('global a = set(1, 2, 3);\n',
+                        'global a = vector("a", "b");\n',
+                        'global a = table([1] = "a", [2] = "b");\n'):
+            self.assertEqual(self._format(content), self._bytes(content))
+
+    def test_long(self):
+        names = ['"element{}"'.format(n) for n in range(8)]
+        content = 'global a = set(' + ', '.join(names) + ');\n'
+        expected = 'global a = set(\n' + ''.join('\t' + n + ',\n' for n in names) + ');\n'
+
+        self.assertEqual(self._format(content), self._bytes(expected))
+        self.assertEqual(self._format(expected), self._bytes(expected))
+
+        # The flag is off by default, so the set wraps like any long line.
+        options = zeekscript.FormatOptions()
+        self.assertNotEqual(zeekscript.format_bytes(self._bytes(content), options),
+                            self._bytes(expected))
+
+    def test_nested(self):
+        # Inner constructors that fit stay on one line.
+        inner = 'vector(1, 2, 3)'
+        content = 'event zeek_init()\n\t{\n\tlocal v = vector(' + ', '.join([inner] * 5) + ');\n\t}\n'
+        expected = ('event zeek_init()\n\t{\n\tlocal v = vector(\n' +
+                    ''.join('\t\t' + inner + ',\n' for _ in range(5)) + '\t);\n\t}\n')
+
+        self.assertEqual(self._format(content), self._bytes(expected))
+
+    def test_narrow(self):
+        content = 'global a = set(1, 2, 3);\n'
+        self.assertEqual(self._format(content, max_line_len=20),
+                         self._bytes('global a = set(\n\t1,\n\t2,\n\t3,\n);\n'))
+
+
+class TestExpandLongSignatures(OptionsTestCase):
+    PARAMS = ['c: connection', 'is_orig: bool', 'payload: string',
+              'context: Analyzer::Info', 'extra: table[string] of string']
+
+    OPTIONS = {'expand_long_signatures': True}
+
+    def test_short(self):
+        for content in ('function f(a: count, b: string): bool\n\t{\n\treturn T;\n\t}\n',
+                        'event e()\n\t{ }\n',
+                        'global e: event(a: count, b: string);\n'):
+            self.assertEqual(self._format(content), self._bytes(content))
+
+    def test_function(self):
+        content = ('function handle(' + ', '.join(self.PARAMS) + '): bool\n'
+                   '\t{\n\treturn T;\n\t}\n')
+        expected = ('function handle(\n' + ',\n'.join('\t' + p for p in self.PARAMS) +
+                    '): bool\n\t{\n\treturn T;\n\t}\n')
+
+        self.assertEqual(self._format(content), self._bytes(expected))
+        self.assertEqual(self._format(expected), self._bytes(expected))
+
+        # The flag is off by default, so the signature wraps like any long line.
+        options = zeekscript.FormatOptions()
+        self.assertNotEqual(zeekscript.format_bytes(self._bytes(content), options),
+                            self._bytes(expected))
+
+    def test_event_hook(self):
+        for kind in ('event', 'hook'):
+            content = kind + ' handle(' + ', '.join(self.PARAMS) + ')\n\t{ }\n'
+            expected = (kind + ' handle(\n' + ',\n'.join('\t' + p for p in self.PARAMS) +
+                        ')\n\t{ }\n')
+            self.assertEqual(self._format(content), self._bytes(expected))
+
+    def test_export(self):
+        params = self.PARAMS[:4]
+        content = 'export {\n\tglobal handle: event(' + ', '.join(params) + ');\n}\n'
+        expected = ('export {\n\tglobal handle: event(\n' +
+                    ',\n'.join('\t\t' + p for p in params) + ');\n}\n')
+        self.assertEqual(self._format(content), self._bytes(expected))
+
+    def test_narrow(self):
+        # Parameters don't split, even when they don't fit by themselves.
+        self.assertEqual(
+            self._format('function f(a: count, b: table[string] of string)\n\t{ }\n',
+                         max_line_len=20),
+            self._bytes('function f(\n\ta: count,\n\tb: table[string] of string)\n\t{ }\n'))
+
+
+class TestExpandLongCalls(OptionsTestCase):
+
+    OPTIONS = {'expand_long_calls': True}
+
+    def _format(self, stmt, **kwargs):
+        return super()._format('event zeek_init()\n\t{\n\t' + stmt + '\n\t}\n', **kwargs)
+
+    def _expected(self, *lines):
+        return self._bytes('event zeek_init()\n\t{\n' + ''.join(line + '\n' for line in lines) +
+                           '\t}\n')
+
+    def test_short(self):
+        self.assertEqual(self._format('Log::write(Conn::LOG, rec);'),
+                         self._expected('\tLog::write(Conn::LOG, rec);'))
+
+    def test_long(self):
+        stmt = 'SumStats::create_something_long(first_argument_value, second_argument_value, third_arg);'
+        expected = self._expected(
+            '\tSumStats::create_something_long(',
+            '\t\tfirst_argument_value,',
+            '\t\tsecond_argument_value,',
+            '\t\tthird_arg);')
+
+        self.assertEqual(self._format(stmt), expected)
+
+        # The flag is off by default, so the call wraps like any long line.
+        content = 'event zeek_init()\n\t{\n\t' + stmt + '\n\t}\n'
+        self.assertNotEqual(zeekscript.format_bytes(self._bytes(content)), expected)
+
+    def test_nested(self):
+        # Each call that doesn't fit adds one level of indentation.
+        bbb, ccc, ddd = 'b' * 30, 'c' * 30, 'd' * 22
+        self.assertEqual(
+            self._format('f(aaaa, g({}, {}, {}));'.format(bbb, ccc, ddd)),
+            self._expected(
+                '\tf(',
+                '\t\taaaa,',
+                '\t\tg(',
+                '\t\t\t{},'.format(bbb),
+                '\t\t\t{},'.format(ccc),
+                '\t\t\t{}));'.format(ddd)))
+
+    def test_constructors(self):
+        # Constructors keep their own layout, with a trailing comma.
+        self.assertEqual(
+            self._format('local s = set(1, 2, 3);', expand_long_constructors=True,
+                         max_line_len=20),
+            self._expected('\tlocal s = set(', '\t\t1,', '\t\t2,', '\t\t3,', '\t);'))
+
+    def test_other_expressions(self):
+        # Expressions other than calls lay out as without the option.
+        for stmt in ('local s = set(1, 2, 3);', 'local t = table([1] = 2, [3] = 4);',
+                     'local v: vector of count = vector(1, 2, 3) &redef;',
+                     'local x = (aaaa + bbbb) * (cccc + dddd);'):
+            for kwargs in ({}, {'expand_long_constructors': True}):
+                options = zeekscript.FormatOptions(max_line_len=20, check_idempotency=True,
+                                                   **kwargs)
+                content = self._bytes('event zeek_init()\n\t{\n\t' + stmt + '\n\t}\n')
+                self.assertEqual(self._format(stmt, max_line_len=20, **kwargs),
+                                 zeekscript.format_bytes(content, options))
+
+
+class TestKeepShortRecords(OptionsTestCase):
+
+    OPTIONS = {'keep_short_records': True}
+
+    def _lines_with(self, result, record):
+        return [line for line in result.splitlines() if record.encode('UTF-8') in line]
+
+    def test_collapse(self):
+        self.assertEqual(self._format('global r = [\n\t$a = 1\n];\n'),
+                         self._bytes('global r = [$a=1];\n'))
+
+    def test_wrap_outside(self):
+        record = '[$orig_h=1.1.1.1, $orig_p=1/tcp]'
+        content = ('event zeek_init()\n\t{\n\tfoo(' + ', '.join(['argument'] * 6) +
+                   ', ' + record + ');\n\t}\n')
+        result = self._format(content)
+
+        self.assertGreater(len(result.splitlines()), 4)
+        self.assertEqual(len(self._lines_with(result, record)), 1)
+
+    def test_nested(self):
+        # The outer record doesn't fit, the inner ones do.
+        inner = ['[$orig_h=1.1.1.{}, $orig_p={}/tcp]'.format(n, n) for n in range(3)]
+        content = 'global r = [' + ', '.join('$f{}={}'.format(n, r)
+                                            for n, r in enumerate(inner)) + '];\n'
+        result = self._format(content)
+
+        self.assertGreater(len(result.splitlines()), 1)
+        for record in inner:
+            self.assertEqual(len(self._lines_with(result, record)), 1)
+
+        # A nested record that fits stays together with its outer one:
+        content = 'global r = [$a=[$b=1, $c=2], $d=3];\n'
+        self.assertEqual(self._format(content), self._bytes(content))
+
+
+class TestBracketSpacing(OptionsTestCase):
+    INPUT = ('global t = table([ 1, 2 ] = "a");\n'
+             'global r = [ $a = v[ 0x01 ], $b = s[ 1 : 3 ] ];\n'
+             'global x = [ 1 ] in t;\n'
+             'global y: table[count] of string;\n')
+
+    def test_default(self):
+        self.assertEqual(self._format(self.INPUT), self._bytes(
+            'global t = table([1, 2] = "a");\n'
+            'global r = [$a=v[0x01], $b=s[1 : 3]];\n'
+            'global x = [1] in t;\n'
+            'global y: table[count] of string;\n'))
+
+    def test_spacing(self):
+        # Record constructors and types keep their brackets unpadded.
+        self.assertEqual(self._format(self.INPUT, bracket_spacing=True), self._bytes(
+            'global t = table([ 1, 2 ] = "a");\n'
+            'global r = [$a=v[ 0x01 ], $b=s[ 1 : 3 ]];\n'
+            'global x = [ 1 ] in t;\n'
+            'global y: table[count] of string;\n'))
+
+
+class TestBraceStyle(OptionsTestCase):
+    # The same script in all three styles. The Whitesmiths one is the default.
+    WHITESMITHS = """function f(x: count)
+	{
+	if ( x > 1 )
+		{
+		print x;
+		}
+	else if ( x > 0 )
+		print 1;
+	else
+		{
+		print 0;
+		}
+
+	for ( i in s )
+		{
+		print i;
+		}
+
+	while ( T )
+		{
+		break;
+		}
+
+	{
+	print 1;
+	}
+	}
+
+event zeek_init()
+	{ }
+"""
+
+    ALLMAN = """function f(x: count)
+{
+	if ( x > 1 )
+	{
+		print x;
+	}
+	else if ( x > 0 )
+		print 1;
+	else
+	{
+		print 0;
+	}
+
+	for ( i in s )
+	{
+		print i;
+	}
+
+	while ( T )
+	{
+		break;
+	}
+
+	{
+		print 1;
+	}
+}
+
+event zeek_init()
+{ }
+"""
+
+    ATTACH = """function f(x: count) {
+	if ( x > 1 ) {
+		print x;
+	} else if ( x > 0 )
+		print 1;
+	else {
+		print 0;
+	}
+
+	for ( i in s ) {
+		print i;
+	}
+
+	while ( T ) {
+		break;
+	}
+
+	{
+		print 1;
+	}
+}
+
+event zeek_init() { }
+"""
+
+    def _format(self, content, style=None):
+        if style is None:
+            return super()._format(content)
+        return super()._format(content, brace_style=style)
+
+    def test_styles(self):
+        styles = [(zeekscript.BraceStyle.WHITESMITHS, self.WHITESMITHS),
+                  (zeekscript.BraceStyle.ALLMAN, self.ALLMAN),
+                  (zeekscript.BraceStyle.ATTACH, self.ATTACH)]
+
+        # Every style's output converts to every other one.
+        for style, expected in styles:
+            for _, content in styles:
+                self.assertEqual(self._format(content, style), self._bytes(expected))
+
+        self.assertEqual(self._format(self.ALLMAN), self._bytes(self.WHITESMITHS))
+
+    def test_switch(self):
+        content = 'event zeek_init() { switch ( x ) { case 1: break; } }\n'
+        expected = """event zeek_init() {
+	switch ( x ) {
+		case 1:
+			break;
+	}
+}
+"""
+        self.assertEqual(self._format(content, zeekscript.BraceStyle.ATTACH),
+                         self._bytes(expected))
+
+    def test_when(self):
+        # Both blocks follow the brace style, and the timeout clause starts a
+        # line of its own at the level of the "when".
+        content = ('function f(h: string): set[addr] { return when [h] ( local a = '
+                   'lookup_hostname(h) ) { return a; } timeout 5 sec { return set(); } }\n')
+
+        whitesmiths = """function f(h: string): set[addr]
+	{
+	return when [h] ( local a = lookup_hostname(h) )
+		{
+		return a;
+		}
+	timeout 5 sec
+		{
+		return set();
+		}
+	}
+"""
+        allman = """function f(h: string): set[addr]
+{
+	return when [h] ( local a = lookup_hostname(h) )
+	{
+		return a;
+	}
+	timeout 5 sec
+	{
+		return set();
+	}
+}
+"""
+        attach = """function f(h: string): set[addr] {
+	return when [h] ( local a = lookup_hostname(h) ) {
+		return a;
+	}
+	timeout 5 sec {
+		return set();
+	}
+}
+"""
+        for style, expected in ((zeekscript.BraceStyle.WHITESMITHS, whitesmiths),
+                                (zeekscript.BraceStyle.ALLMAN, allman),
+                                (zeekscript.BraceStyle.ATTACH, attach)):
+            self.assertEqual(self._format(content, style), self._bytes(expected))
+
+    def test_from_toml(self):
+        opts = zeekscript.FormatOptions.from_toml('brace_style = "allman"\n')
+        self.assertEqual(opts.brace_style, zeekscript.BraceStyle.ALLMAN)
+
+
+class TestTernary(OptionsTestCase):
+
+    def _format(self, expr):
+        return super()._format('event zeek_init()\n\t{\n\tlocal x = ' + expr + ';\n\t}\n')
+
+    def _expected(self, *lines):
+        return self._bytes('event zeek_init()\n\t{\n' + ''.join(line + '\n' for line in lines) +
+                           '\t}\n')
+
+    def test_short(self):
+        self.assertEqual(self._format('a ? b : c'), self._expected('\tlocal x = a ? b : c;'))
+
+    def test_long(self):
+        expr = 'really_long_condition(aaaa, bbbb) ? some_long_value(cccc, dddd) : other_value(eeee)'
+        self.assertEqual(self._format(expr), self._expected(
+            '\tlocal x = really_long_condition(aaaa, bbbb)',
+            '\t\t? some_long_value(cccc, dddd)',
+            '\t\t: other_value(eeee);'))
+
+    def test_nested(self):
+        # The inner conditional doesn't fit its line either.
+        expr = ('cond_one_is_long(aaaa) ? value_one(bbbb) : cond_two_is_long(cccccccccc) '
+                '? value_two(dddddddddd) : value_three(eeeeeeeeee)')
+        self.assertEqual(self._format(expr), self._expected(
+            '\tlocal x = cond_one_is_long(aaaa)',
+            '\t\t? value_one(bbbb)',
+            '\t\t: cond_two_is_long(cccccccccc)',
+            '\t\t\t? value_two(dddddddddd)',
+            '\t\t\t: value_three(eeeeeeeeee);'))
+
+
+class TestOperatorPosition(OptionsTestCase):
+    CONDITION = 'a_long_var_a in a_long_var_b && ( c in d || e in f ) || a_long_var_g in a_long_var_h'
+
+    def _format(self, condition, **kwargs):
+        return super()._format(
+            'event zeek_init()\n\t{\n\tif ( ' + condition + ' )\n\t\tprint 1;\n\t}\n', **kwargs)
+
+    def _expected(self, *lines):
+        return self._bytes('event zeek_init()\n\t{\n' + ''.join(line + '\n' for line in lines) +
+                           '\t\tprint 1;\n\t}\n')
+
+    def test_leading(self):
+        # Parenthesized operands stay intact.
+        self.assertEqual(self._format(self.CONDITION), self._expected(
+            '\tif ( a_long_var_a in a_long_var_b',
+            '\t    && ( c in d || e in f )',
+            '\t    || a_long_var_g in a_long_var_h )'))
+
+    def test_trailing(self):
+        position = zeekscript.OperatorPosition.TRAILING
+        self.assertEqual(self._format(self.CONDITION, operator_position=position), self._expected(
+            '\tif ( a_long_var_a in a_long_var_b &&',
+            '\t    ( c in d || e in f ) ||',
+            '\t    a_long_var_g in a_long_var_h )'))
+
+        # Conditions that fit remain on one line:
+        self.assertEqual(self._format('a in b && c || d', operator_position=position),
+                         self._expected('\tif ( a in b && c || d )'))
+
+    def test_from_toml(self):
+        opts = zeekscript.FormatOptions.from_toml('operator_position = "trailing"\n')
+        self.assertEqual(opts.operator_position, zeekscript.OperatorPosition.TRAILING)
+
+
+class TestSwitchCaseIndent(OptionsTestCase):
+
+    SCRIPT = ('event zeek_init() { switch ( x ) { case 1: print 1; fallthrough; '
+              'case 2: case 3: break; } switch ( y ) { default: print 2; } }\n')
+
+    def _format(self, **kwargs):
+        return super()._format(self.SCRIPT, **kwargs)
+
+    def test_indented(self):
+        expected = """event zeek_init()
+	{
+	switch ( x )
+		{
+		case 1:
+			print 1;
+			fallthrough;
+		case 2:
+		case 3:
+			break;
+		}
+	switch ( y )
+		{
+		default:
+			print 2;
+		}
+	}
+"""
+        self.assertEqual(self._format(), self._bytes(expected))
+        self.assertEqual(self._format(switch_case_indent=zeekscript.SwitchCaseIndent.INDENTED),
+                         self._bytes(expected))
+
+    def test_same_as_switch(self):
+        expected = """event zeek_init()
+	{
+	switch ( x )
+		{
+	case 1:
+		print 1;
+		fallthrough;
+	case 2:
+	case 3:
+		break;
+		}
+	switch ( y )
+		{
+	default:
+		print 2;
+		}
+	}
+"""
+        self.assertEqual(self._format(
+            switch_case_indent=zeekscript.SwitchCaseIndent.SAME_AS_SWITCH), self._bytes(expected))
+
+    def test_from_toml(self):
+        opts = zeekscript.FormatOptions.from_toml('switch_case_indent = "same_as_switch"\n')
+        self.assertEqual(opts.switch_case_indent, zeekscript.SwitchCaseIndent.SAME_AS_SWITCH)
+
+        with self.assertRaisesRegex(zeekscript.ConfigError, 'invalid switch case indentation "flat"'):
+            zeekscript.FormatOptions.from_toml('switch_case_indent = "flat"\n')
+
+
+class TestHexCase(OptionsTestCase):
+    SCRIPT = 'global a = 0x01;\nglobal b = 0x0a;\nglobal c = 0x0B;\nglobal d = 0xfF;\n'
+
+    def test_preserve(self):
+        self.assertEqual(self._format(self.SCRIPT), self._bytes(self.SCRIPT))
+
+    def test_upper(self):
+        self.assertEqual(self._format(self.SCRIPT, hex_case=zeekscript.HexCase.UPPER),
+                         self._bytes(self.SCRIPT.replace('0x0a', '0x0A').replace('0xfF', '0xFF')))
+
+    def test_lower(self):
+        self.assertEqual(self._format(self.SCRIPT, hex_case=zeekscript.HexCase.LOWER),
+                         self._bytes(self.SCRIPT.replace('0x0B', '0x0b').replace('0xfF', '0xff')))
+
+    def test_from_toml(self):
+        opts = zeekscript.FormatOptions.from_toml('hex_case = "upper"\n')
+        self.assertEqual(opts.hex_case, zeekscript.HexCase.UPPER)
+
+
+class TestExportBlocks(OptionsTestCase):
+
+    def test_blank_lines_inside(self):
+        self.assertEqual(self._format('export {\n\n\tglobal a: count;\n\n}\n'),
+                         self._bytes('export {\n\tglobal a: count;\n}\n'))
+
+    def test_blank_line_after(self):
+        self.assertEqual(self._format('export {\n\tglobal a: count;\n}\nglobal b: count;\n'),
+                         self._bytes('export {\n\tglobal a: count;\n}\n\nglobal b: count;\n'))
+
+        # An existing blank line remains a single one.
+        self.assertEqual(self._format('export {\n\tglobal a: count;\n}\n\nglobal b: count;\n'),
+                         self._bytes('export {\n\tglobal a: count;\n}\n\nglobal b: count;\n'))
+
+    def test_comment_after(self):
+        # A comment trailing the block stays on its line, one on the next
+        # line belongs to what follows.
+        self.assertEqual(self._format('export { } # Nothing yet\n# b\nglobal b: count;\n'),
+                         self._bytes('export { } # Nothing yet\n\n# b\nglobal b: count;\n'))
+
+    def test_empty(self):
+        self.assertEqual(self._format('export {\n\n}\n'), self._bytes('export { }\n'))
+
+        # With comments inside, the block stays as it is.
+        content = 'export {\n\t# Nothing yet.\n}\n'
+        self.assertEqual(self._format(content), self._bytes(content))
+
+
+class TestZeekygenPrevComments(OptionsTestCase):
+
+    def test_after_statement(self):
+        # Both comments stay with the global, the second aligned to the first.
+        self.assertEqual(
+            self._format('global a = 1;##< foo\n##< bar\nglobal b = 2;\n'),
+            self._bytes('global a = 1; ##< foo\n              ##< bar\nglobal b = 2;\n'))
+
+    def test_after_attribute(self):
+        content = 'global a: count &redef; ##< foo\nglobal b = 2;\n'
+        self.assertEqual(self._format(content), self._bytes(content))
+
+    def test_record_field(self):
+        self.assertEqual(
+            self._format('type R: record {\n\ta: count &optional;##< foo\n'
+                         '\t##< bar\n\tb: count; ##< baz\n};\n'),
+            self._bytes('type R: record {\n\ta: count &optional; ##< foo\n'
+                        '\t                    ##< bar\n\tb: count; ##< baz\n};\n'))
+
+    def test_enum_value(self):
+        self.assertEqual(
+            self._format('type E: enum {\n\tA,##< foo\n##< bar\n\tB, ##< baz\n};\n'),
+            self._bytes('type E: enum {\n\tA, ##< foo\n\t   ##< bar\n\tB, ##< baz\n};\n'))
+
+
+class TestPreprocDirectives(OptionsTestCase):
+
+    def test_around_handlers(self):
+        # The handlers keep their regular indentation. check_idempotency
+        # verifies that this holds on a second pass, too.
+        content = ('@if ( Version::at_least("6.0") )\n'
+                   'event zeek_init()\n\t{\n\tprint "hi";\n\t}\n'
+                   '@else\n'
+                   'event zeek_init()\n\t{\n\tprint "bye";\n\t}\n'
+                   '@endif\n')
+        self.assertEqual(self._format(content), self._bytes(content))
+
+    def test_in_body(self):
+        self.assertEqual(
+            self._format('event zeek_init()\n\t{\n\t@ifdef ( Foo::bar )\n'
+                         '\tprint "hi";\n\t@endif\n\t}\n'),
+            self._bytes('event zeek_init()\n\t{\n@ifdef ( Foo::bar )\n'
+                        '\tprint "hi";\n@endif\n\t}\n'))
+
+    def test_indented(self):
+        # Directives take the indentation of their surroundings, without
+        # affecting the code they guard, including when nested.
+        content = ('event zeek_init()\n\t{\n@if ( A )\n@ifdef ( B )\n\tprint "hi";\n'
+                   '@endif\n@else\n\tprint "bye";\n@endif\n\t}\n')
+        options = zeekscript.FormatOptions(indent_preproc_directives=True,
+                                           check_idempotency=True)
+        self.assertEqual(
+            zeekscript.format_bytes(self._bytes(content), options),
+            self._bytes('event zeek_init()\n\t{\n\t@if ( A )\n\t@ifdef ( B )\n\tprint "hi";\n'
+                        '\t@endif\n\t@else\n\tprint "bye";\n\t@endif\n\t}\n'))
+
+        # Toplevel directives stay at the beginning of the line.
+        content = '@if ( A )\nglobal a = 1;\n@endif\n'
+        self.assertEqual(zeekscript.format_bytes(self._bytes(content), options),
+                         self._bytes(content))
+
+
+class TestFormatDirectives(OptionsTestCase):
+    BEFORE = 'global  a=1;\n'
+    AFTER = 'global  b=2;\n'
+    TABLE = """# zeekfmt: off
+const codes = {
+    [1]  = "one",
+    [22] = "twenty-two",
+};
+# zeekfmt: on
+"""
+
+    def test_region(self):
+        result = self._format(self.BEFORE + self.TABLE + self.AFTER)
+        self.assertEqual(result, self._format(self.BEFORE) + self._bytes(self.TABLE) +
+                         self._format(self.AFTER))
+
+        # Formatting is stable:
+        self.assertEqual(result, zeekscript.format_bytes(result))
+
+    def test_off_without_on(self):
+        table = self.TABLE.replace('# zeekfmt: on\n', '')
+        result = self._format(self.BEFORE + table + self.AFTER)
+        self.assertEqual(result, self._format(self.BEFORE) + self._bytes(table + self.AFTER))
+
+    def test_on_without_off(self):
+        content = self.BEFORE + '# zeekfmt: on\n' + self.AFTER
+        self.assertEqual(self._format(content),
+                         self._format(self.BEFORE + '# zeekfmt: on\n') + self._format(self.AFTER))
+
+    def test_nested(self):
+        content = ('# zeekfmt: off\n' + self.BEFORE + self.TABLE + self.AFTER +
+                   '# zeekfmt: on\n')
+        self.assertEqual(self._format(content), self._bytes(content))
+
+    def test_zeek_format_spelling(self):
+        table = self.TABLE.replace('zeekfmt', 'zeek-format')
+        content = self._bytes(self.BEFORE + table + self.AFTER)
+        result = zeekscript.format_bytes(
+            content, zeekscript.FormatOptions(check_idempotency=True))
+        self.assertEqual(result, self._format(self.BEFORE) + self._bytes(table) +
+                         self._format(self.AFTER))
+
+        # A nested off is harmless, and without its on runs through the end:
+        content = ('# zeek-format: off\n' + self.BEFORE + '# zeekfmt: off\n' +
+                   self.AFTER + '# zeek-format: on\n')
+        self.assertEqual(self._format(content), self._bytes(content))
+
+    def test_not_a_directive(self):
+        # Directives need their own line.
+        content = 'global  a=1; # zeekfmt: off\n' + self.AFTER
+        self.assertNotIn(b'global  b=2;', self._format(content))
+
+
+class TestShebang(OptionsTestCase):
+
+    def test_script(self):
+        self.assertEqual(self._format('#!/usr/bin/env zeek\n\nevent zeek_init() { print 1; }\n'),
+                         self._bytes('#!/usr/bin/env zeek\n\nevent zeek_init()\n\t{\n\tprint 1;\n\t}\n'))
+
+    def test_verbatim(self):
+        # Trailing whitespace could matter to the shebang's arguments.
+        self.assertEqual(self._format('#!/usr/bin/env -S zeek -C  \nglobal  a=1;\n'),
+                         self._bytes('#!/usr/bin/env -S zeek -C  \nglobal a = 1;\n'))
+
+    def test_module_comment(self):
+        content = '#!/usr/bin/env zeek\n##! A module.\n##! More about it.\n\nglobal a = 1;\n'
+        self.assertEqual(self._format(content), self._bytes(content))
+
+        # Reflowing documentation leaves the shebang alone, too.
+        self.assertEqual(self._format(content, reflow_doc_comments=True),
+                         self._bytes('#!/usr/bin/env zeek\n##! A module. More about it.\n\n'
+                                     'global a = 1;\n'))
+
+    def test_no_newline(self):
+        self.assertEqual(self._format('#!/usr/bin/env zeek'), self._bytes('#!/usr/bin/env zeek\n'))
+        self.assertEqual(self._format('#!/usr/bin/env zeek', final_newline=False),
+                         b'#!/usr/bin/env zeek')
+
+    def test_not_first(self):
+        # Only the first line can be a shebang.
+        self.assertEqual(self._format('global a = 1;\n#!  not a shebang  \n'),
+                         self._bytes('global a = 1;\n#!  not a shebang\n'))
+
+
+class TestFinalNewline(OptionsTestCase):
+
+    def test_missing_newline(self):
+        self.assertEqual(self._format('global a = 1;'), self._bytes('global a = 1;\n'))
+
+    def test_extra_newlines(self):
+        self.assertEqual(self._format('global a = 1;\n\n\n'), self._bytes('global a = 1;\n'))
+
+    def test_trailing_whitespace(self):
+        self.assertEqual(self._format('# A comment\t\n#\t\nglobal a = 1;  \n'),
+                         self._bytes('# A comment\n#\nglobal a = 1;\n'))
+
+    def test_disabled_region(self):
+        # Formatting stays off through the end, but the trailing blank lines go.
+        content = '# zeekfmt: off\nglobal  a=1;\n'
+        self.assertEqual(self._format(content + '\n\n'), self._bytes(content))
+        self.assertEqual(self._format(content.rstrip()), self._bytes(content))
+
+    def test_no_final_newline(self):
+        self.assertEqual(self._format('global a = 1;\n\n', final_newline=False),
+                         self._bytes('global a = 1;'))
+        self.assertEqual(self._format('', final_newline=False), b'')
+
+    def test_crlf(self):
+        for content in ('global a = 1;', 'global a = 1;\n\n\n'):
+            self.assertEqual(self._format(content, line_ending=zeekscript.LineEnding.CRLF),
+                             b'global a = 1;\r\n')
+
+    def test_entry_points(self):
+        # All ways of formatting end the script the same way.
+        expected = self._bytes('global a = 1;\n')
+
+        for content in (self._bytes('global a = 1;'), self._bytes('global a = 1;\n\n\n')):
+            out = io.BytesIO()
+            zeekscript.format_stream(io.BytesIO(content), out)
+            self.assertEqual(out.getvalue(), expected)
+
+            script = zeekscript.Script(io.BytesIO(content))
+            script.parse()
+            out = io.BytesIO()
+            script.format(out)
+            self.assertEqual(out.getvalue(), expected)
+
+            with tempfile.TemporaryDirectory() as tmpdir:
+                path = os.path.join(tmpdir, 'test.zeek')
+                with open(path, 'wb') as hdl:
+                    hdl.write(content)
+                self.assertEqual(zeekscript.format_file(path), expected)
+
+            opts = zeekscript.FormatOptions(check_idempotency=True)
+            self.assertEqual(zeekscript.format_bytes(content, opts), expected)
+
+
+class TestFormatNode(OptionsTestCase):
+
+    def test_decl(self):
+        expected = self._bytes('function f(a: count): count\n\t{\n\treturn a + 1;\n\t}\n')
+        for kind in ('decl', 'func_decl'):
+            self.assertEqual(zeekscript.format_node(kind, 'function f(a:count):count{return a+1;}'),
+                             expected)
+
+        with self.assertRaises(zeekscript.ParserError):
+            zeekscript.format_node('type_decl', 'global a = 1;')
+
+    def test_stmt(self):
+        self.assertEqual(zeekscript.format_node('stmt', 'if(a)print  "x";'),
+                         self._bytes('if ( a )\n\tprint "x";\n'))
+
+        # Other brace styles and indentation work as well.
+        options = zeekscript.FormatOptions(brace_style=zeekscript.BraceStyle.ATTACH, indent=4)
+        self.assertEqual(zeekscript.format_node('stmt', 'if(a)print  "x";', options),
+                         self._bytes('if ( a )\n    print "x";\n'))
+
+        with self.assertRaises(zeekscript.ParserError):
+            zeekscript.format_node('stmt', 'local a = 1; print a;')
+
+    def test_stmt_list(self):
+        self.assertEqual(zeekscript.format_node('stmt_list', 'local a = 1; print a;'),
+                         self._bytes('local a = 1;\nprint a;\n'))
+
+    def test_expr(self):
+        self.assertEqual(zeekscript.format_node('expr', 'a+b*  c'), b'a + b * c')
+
+        with self.assertRaises(zeekscript.ParserError):
+            zeekscript.format_node('expr', 'a +')
+
+    def test_unsupported(self):
+        with self.assertRaises(ValueError):
+            zeekscript.format_node('attr_list', '&redef')
+
+
+class TestParseBytes(unittest.TestCase):
+    CONTENT = 'global  a = 1; # One\n'
+
+    def test_parse(self):
+        script = zeekscript.parse_bytes(self.CONTENT)
+        self.assertFalse(script.has_error())
+        self.assertEqual(script.root.type, 'source_file')
+        self.assertIsNotNone(script.ts_tree)
+
+    def test_node_at_byte(self):
+        script = zeekscript.parse_bytes(self.CONTENT)
+
+        node = script.node_at_byte(self.CONTENT.index('a'))
+        self.assertEqual(script.get_content(*node.script_range()), b'a')
+
+        node = script.node_at_byte(self.CONTENT.index('1'))
+        self.assertEqual(script.get_content(*node.script_range()), b'1')
+
+        self.assertIsNone(script.node_at_byte(len(self.CONTENT) + 10))
+
+    def test_node_at_byte_cst(self):
+        script = zeekscript.parse_bytes(self.CONTENT)
+        offset = self.CONTENT.index('One')
+
+        node = script.node_at_byte(offset, include_cst=True)
+        self.assertTrue(node.is_comment())
+
+    def test_declarations(self):
+        content = ('module Foo;\n\nexport {\n\tglobal bar: count;\n\tconst baz = 1;\n}\n\n'
+                   'type R: record { a: count; };\nfunction f() { }\nevent zeek_init() { }\n'
+                   'hook h() { }\nredef Foo::baz = 2;\nprint 1;\n')
+        script = zeekscript.parse_bytes(content)
+        decls = list(script.declarations())
+
+        self.assertEqual([(decl.kind, decl.name) for decl in decls], [
+            ('module', 'Foo'), ('global', 'bar'), ('const', 'baz'), ('type', 'R'),
+            ('function', 'f'), ('event', 'zeek_init'), ('hook', 'h'), ('redef', 'Foo::baz')])
+
+        self.assertEqual(script.get_content(decls[4].start_byte, decls[4].end_byte),
+                         b'function f() { }')
+
+
+class TestByteOrderMark(OptionsTestCase):
+    BOM = zeekscript.UTF8_BOM
+
+    def test_roundtrip(self):
+        expected = self.BOM + self._bytes('global a = 1;\n')
+
+        self.assertEqual(zeekscript.format_bytes(self.BOM + b'global  a=1;\n'), expected)
+        self.assertEqual(zeekscript.format_bytes('\ufeffglobal  a=1;\n'), expected)
+        self.assertEqual(zeekscript.format_bytes(expected), expected)
+        self.assertTrue(zeekscript.is_formatted(expected))
+
+        # Without a mark in the input, there's none in the output:
+        self.assertEqual(zeekscript.format_bytes(b'global  a=1;\n'), expected[len(self.BOM):])
+
+    def test_script(self):
+        script = zeekscript.parse_bytes(self.BOM + b'global a = 1;\n')
+        self.assertFalse(script.has_error())
+        self.assertEqual(script.bom, self.BOM)
+        self.assertEqual(script.get_content(), b'global a = 1;\n')
+        self.assertEqual(script.get_original_content(), self.BOM + b'global a = 1;\n')
+
+    def test_options(self):
+        content = self.BOM + b'global  a=1;\r\nglobal  b=2;\r\n'
+        opts = zeekscript.FormatOptions(check_idempotency=True)
+        self.assertEqual(zeekscript.format_bytes(content, opts),
+                         self.BOM + b'global a = 1;\r\nglobal b = 2;\r\n')
+
+        broken = self.BOM + b'event zeek_init() { foo)(); }'
+        opts = zeekscript.FormatOptions(passthrough_on_error=True)
+        self.assertEqual(zeekscript.format_bytes(broken, opts), broken)
+
+    def test_drop(self):
+        opts = zeekscript.FormatOptions(keep_byte_order_mark=False)
+        self.assertEqual(zeekscript.format_bytes(self.BOM + self._bytes('global  a=1;\n'), opts),
+                         self._bytes('global a = 1;\n'))
+        self.assertFalse(zeekscript.is_formatted(self.BOM + self._bytes('global a = 1;\n'), opts))
+
+        # Range edits leave the mark alone.
+        source = self.BOM + self._bytes('global  a=1;\n')
+        edit = zeekscript.format_range(source, len(self.BOM), len(self.BOM) + 1, opts)
+        self.assertEqual(edit.actual_start, len(self.BOM))
+        self.assertEqual(edit.apply(source), self.BOM + self._bytes('global a = 1;\n'))
+
+    def test_invalid_utf8(self):
+        # Stray bytes don't keep us from reporting problems.
+        script = zeekscript.parse_bytes(b'global a = "\xff";\nevent zeek_init() { \xff)(); }\n')
+        line, lineno, _ = script.get_error()
+        self.assertEqual(lineno, 1)
+        self.assertIn('\ufffd', line)
+
+        # Text streams require valid UTF-8, so writing to them pinpoints
+        # where it's lacking.
+        script = zeekscript.parse_bytes(b'global a = "\xff";\n')
+        with self.assertRaises(zeekscript.EncodingError) as ctx:
+            script.format(io.StringIO())
+        self.assertEqual(ctx.exception.offset, 12)
+
+
+class TestValidation(unittest.TestCase):
+
+    def test_valid(self):
+        content = 'event zeek_init() { print "hi"; }'
+        self.assertTrue(zeekscript.is_valid_syntax(content))
+        self.assertTrue(zeekscript.is_valid_syntax(content.encode('UTF-8')))
+        self.assertEqual(zeekscript.parse_errors(content), [])
+
+    def test_empty(self):
+        self.assertTrue(zeekscript.is_valid_syntax(''))
+        self.assertEqual(zeekscript.parse_errors(''), [])
+
+    def test_invalid(self):
+        content = 'event zeek_init() { foo)(); }'
+        self.assertFalse(zeekscript.is_valid_syntax(content))
+        self.assertEqual(zeekscript.parse_errors(content), [
+            zeekscript.Diagnostic('cannot parse line 0, col 23: ")"', 23, 24, 0, 23, 'ERROR')])
+
+
+class TestFormatEdits(unittest.TestCase):
+    # These tests use ASCII only, so characters and UTF-16 code units agree.
+    NL = zeekscript.Formatter.NL.decode('UTF-8')
+
+    def _apply(self, source, edits):
+        lines = source.splitlines(keepends=True)
+
+        def offset(pos):
+            return sum(len(line) for line in lines[:pos.line]) + pos.character
+
+        # Apply from the back, so earlier offsets remain valid.
+        for edit in reversed(edits):
+            source = source[:offset(edit.start)] + edit.new_text + source[offset(edit.end):]
+        return source
+
+    def test_formatted(self):
+        self.assertEqual(zeekscript.format_edits(zeekscript.format_bytes('global a = 1;\n')), [])
+
+    def test_minimal(self):
+        source = 'global a = 1;\nglobal  b=2;\nglobal c = 3;\n'.replace(
+            '\n', self.NL)
+        self.assertEqual(zeekscript.format_edits(source), [
+            zeekscript.TextEdit(zeekscript.Position(1), zeekscript.Position(2),
+                                'global b = 2;' + self.NL)])
+
+    def test_apply(self):
+        for source in ('global  a=1;\nglobal b = 2;\n\n\n\nglobal  c=3;',
+                       'event zeek_init() { print  1; }\n',
+                       '\n\nglobal a = 1;\n'):
+            source = source.replace('\n', self.NL)
+            edits = zeekscript.format_edits(source)
+            self.assertTrue(edits)
+            self.assertEqual(self._apply(source, edits),
+                             zeekscript.format_bytes(source).decode('UTF-8'))
+
+    def test_final_line(self):
+        # An edit covering a final line without newline ends after its text.
+        edits = zeekscript.format_edits('global  a=1;')
+        self.assertEqual(edits, [zeekscript.TextEdit(
+            zeekscript.Position(0), zeekscript.Position(0, 12), 'global a = 1;' + self.NL)])
+
+
+class TestFormatRange(OptionsTestCase):
+    LINES = ['global  a=1;\n', 'global  b=2;\n', 'global  c=3;\n']
+
+    def setUp(self):
+        self.lines = [self._bytes(line) for line in self.LINES]
+        self.source = b''.join(self.lines)
+
+    def test_single(self):
+        start = len(self.lines[0])
+        edit = zeekscript.format_range(self.source, start + 9, start + 10)
+        self.assertEqual(edit, zeekscript.RangeEdit(
+            zeekscript.format_bytes(self.lines[1]), start, start + len(self.lines[1])))
+
+        self.assertEqual(edit.apply(self.source), self.lines[0] +
+                         zeekscript.format_bytes(self.lines[1]) + self.lines[2])
+
+    def test_cursor(self):
+        start = len(self.lines[0])
+        edit = zeekscript.format_range(self.source, start + 3, start + 3)
+        self.assertEqual(edit.actual_start, start)
+        self.assertEqual(edit.actual_end, start + len(self.lines[1]))
+
+    def test_multiple(self):
+        edit = zeekscript.format_range(self.source, 3, len(self.lines[0]) + 3)
+        self.assertEqual(edit.actual_start, 0)
+        self.assertEqual(edit.actual_end, len(self.lines[0]) + len(self.lines[1]))
+        self.assertEqual(edit.apply(self.source),
+                         zeekscript.format_bytes(self.lines[0] + self.lines[1]) +
+                         self.lines[2])
+
+    def test_shared_line(self):
+        # Two globals on one line get formatted together.
+        source = self._bytes('global  a=1; global  b=2;\nglobal  c=3;\n')
+        edit = zeekscript.format_range(source, 0, 1)
+        self.assertEqual(edit.actual_start, 0)
+        self.assertEqual(edit.actual_end, source.index(b'global  c'))
+
+    def test_lines(self):
+        result = zeekscript.format_lines(self.source, 1, 1)
+        self.assertEqual(result, self.lines[0] + zeekscript.format_bytes(self.lines[1]) +
+                         self.lines[2])
+
+        result = zeekscript.format_lines(self.source, 0, 2)
+        self.assertEqual(result, zeekscript.format_bytes(self.source))
+
+        with self.assertRaises(ValueError):
+            zeekscript.format_lines(self.source, 2, 1)
+        with self.assertRaises(ValueError):
+            zeekscript.format_lines(self.source, 0, 10)
+
+    def test_statements(self):
+        # Statements in bodies format at their depth, with the rest unchanged.
+        lines = ['global  a=1;\n', 'event zeek_init()\n', '{\n', 'print  1;\n',
+                 '  print  2;\n', 'if ( T ) {\n', 'print  3;\n', '}\n', '}\n']
+        lines = [self._bytes(line) for line in lines]
+        source = b''.join(lines)
+
+        def replaced(idx, line):
+            return b''.join(lines[:idx] + [self._bytes(line)] + lines[idx+1:])
+
+        self.assertEqual(zeekscript.format_lines(source, 3, 3), replaced(3, '\tprint 1;\n'))
+        self.assertEqual(zeekscript.format_lines(source, 6, 6), replaced(6, '\t\tprint 3;\n'))
+
+        # A selection within a statement covers all of it:
+        start = source.index(b'print  2') + 2
+        edit = zeekscript.format_range(source, start, start + 3)
+        self.assertEqual(edit.apply(source), replaced(4, '\tprint 2;\n'))
+
+    def test_statements_sharing_lines(self):
+        # When statements share lines with their braces, the whole handler
+        # gets formatted.
+        source = self._bytes('global  a=1;\nevent zeek_init() { print  1; }\n')
+        self.assertEqual(zeekscript.format_lines(source, 1, 1),
+                         self._bytes('global  a=1;\n') +
+                         zeekscript.format_bytes(self._bytes('event zeek_init() { print  1; }\n')))
+
+    def test_byte_order_mark(self):
+        source = zeekscript.UTF8_BOM + self.source
+        result = zeekscript.format_lines(source, 0, 0)
+        self.assertEqual(result, zeekscript.UTF8_BOM + zeekscript.format_bytes(self.lines[0]) +
+                         self.lines[1] + self.lines[2])
+
+        start = len(zeekscript.UTF8_BOM) + len(self.lines[0])
+        edit = zeekscript.format_range(source, start + 9, start + 10)
+        self.assertEqual(edit.actual_start, start)
+        self.assertEqual(edit.actual_end, start + len(self.lines[1]))
+
+    def test_lines_in_preproc_block(self):
+        source = self._bytes('@if ( T )\nglobal  a=1;\nglobal  b=2;\n@endif\nglobal  c=3;\n')
+        result = zeekscript.format_lines(source, 2, 2)
+
+        # Only the second global changes:
+        lines = source.split(zeekscript.Formatter.NL)
+        self.assertTrue(result.startswith(zeekscript.Formatter.NL.join(lines[:2])))
+        self.assertTrue(result.endswith(zeekscript.Formatter.NL.join(lines[3:])))
+        self.assertIn(b'global b = 2;', result)
+
+    def test_parse_error(self):
+        source = self._bytes('global  a=1;\nfunction foo) { }\n')
+        with self.assertRaises(zeekscript.ParserError):
+            zeekscript.format_lines(source, 0, 0)
+
+    def test_invalid(self):
+        with self.assertRaises(ValueError):
+            zeekscript.format_range(self.source, 5, len(self.source) + 1)
+        with self.assertRaises(ValueError):
+            zeekscript.format_range(self.source, 5, 4)
+
+
+class TestScriptConstruction(unittest.TestCase):
+    DATA = 'event zeek_init() { }'
+    TMPFILE = 'tmp.zeek'
+
+    def test_file(self):
+        # tempfile.NamedTemporaryFile doesn't seem to support reading while
+        # still existing on some platforms, so going manual here:
+        try:
+            with open(self.TMPFILE, 'w') as hdl:
+                hdl.write(self.DATA)
+            script = zeekscript.Script(self.TMPFILE)
+            script.parse()
+        finally:
+            os.unlink(self.TMPFILE)
+
+    def test_path(self):
+        try:
+            with open(self.TMPFILE, 'w') as hdl:
+                hdl.write(self.DATA)
+            script = zeekscript.Script(pathlib.Path(hdl.name))
+            script.parse()
+        finally:
+            os.unlink(self.TMPFILE)
+
+    def test_stdin(self):
+        try:
+            oldstdin = sys.stdin
+            sys.stdin = io.TextIOWrapper(io.BytesIO(self.DATA.encode('UTF-8')))
+            script = zeekscript.Script('-')
+            script.parse()
+        finally:
+            sys.stdin = oldstdin
+
+    def test_text_fileobj(self):
+        obj = io.StringIO(self.DATA)
+        script = zeekscript.Script(obj)
+        script.parse()
+
+    def test_bytes_fileobj(self):
+        obj = io.BytesIO(self.DATA.encode('UTF-8'))
+        script = zeekscript.Script(obj)
+        script.parse()
+
+
+class TestMetadata(unittest.TestCase):
+
+    def test_metadata(self):
+        meta = zeekscript.metadata()
+        self.assertEqual(meta.formatter_version, zeekscript.__version__)
+
+        # The tests require built bindings, so these have a hash:
+        self.assertRegex(meta.grammar_hash, '^[0-9a-f]{64}$')
+        self.assertEqual(meta.grammar_hash, zeekscript.metadata().grammar_hash)
 
 
 def test():
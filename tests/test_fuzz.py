@@ -0,0 +1,107 @@
+#! /usr/bin/env python
+import os
+import random
+import sys
+import unittest
+
+from os.path import join
+
+TESTS = os.path.dirname(os.path.realpath(__file__))
+ROOT = os.path.normpath(join(TESTS, '..'))
+DATA = os.path.normpath(join(TESTS, 'data'))
+
+# Prepend the tree's root folder to the module searchpath so we find zeekscript
+# via it. This allows tests to run without package installation. (We do need a
+# package build though, so the .so bindings library gets created.)
+sys.path.insert(0, ROOT)
+
+import zeekscript
+
+class TestFuzz(unittest.TestCase):
+    """Formats randomly broken variants of the scripts in tests/data.
+
+    Whatever the input, formatting must either succeed or fail with a
+    zeekscript.Error, and input that parses cleanly must format to a stable
+    result. The seed keeps runs repeatable. To explore further, set the
+    ZEEKSCRIPT_FUZZ_SEED and ZEEKSCRIPT_FUZZ_ITERATIONS environment
+    variables. Failures report the seed and the offending input.
+    """
+    SEED = int(os.environ.get('ZEEKSCRIPT_FUZZ_SEED', '0'))
+    ITERATIONS = int(os.environ.get('ZEEKSCRIPT_FUZZ_ITERATIONS', '100'))
+
+    # Snippets that tend to confuse parsers, inserted at random spots.
+    FRAGMENTS = [b'{', b'}', b'(', b')', b'[', b']', b';', b',', b'$', b'&', b'=',
+                 b'"', b'#', b'##', b'##<', b'\n', b'\r\n', b'\t', b'@if ( T )',
+                 b'@else', b'@endif', b'function', b'event', b'when', b'&default=',
+                 b'\xef\xbb\xbf', b'\xff']
+
+    def setUp(self):
+        self.corpus = []
+        for name in sorted(os.listdir(DATA)):
+            with open(join(DATA, name), 'rb') as hdl:
+                self.corpus.append(hdl.read())
+
+        self.rng = random.Random(self.SEED)
+
+    def _mutate(self, source):
+        lines = source.splitlines(keepends=True) or [b'']
+
+        for _ in range(self.rng.randint(1, 3)):
+            idx = self.rng.randrange(len(lines))
+            kind = self.rng.randrange(5)
+
+            if kind == 0:
+                del lines[idx]
+                lines = lines or [b'']
+            elif kind == 1:
+                lines.insert(self.rng.randrange(len(lines) + 1), lines[idx])
+            elif kind == 2:
+                lines[idx] = lines[idx][:self.rng.randrange(len(lines[idx]) + 1)]
+            elif kind == 3:
+                pos = self.rng.randrange(len(lines[idx]) + 1)
+                lines[idx] = (lines[idx][:pos] + self.rng.choice(self.FRAGMENTS) +
+                              lines[idx][pos:])
+            else:
+                # Random bytes, which need not be valid UTF-8.
+                lines.insert(idx, bytes(self.rng.randrange(256)
+                                        for _ in range(self.rng.randint(1, 8))))
+
+        return b''.join(lines)
+
+    def _check(self, source):
+        try:
+            zeekscript.parse_errors(source)
+        except zeekscript.ParserError:
+            pass
+
+        try:
+            zeekscript.format_bytes(source)
+        except zeekscript.Error:
+            return
+
+        if zeekscript.is_valid_syntax(source):
+            zeekscript.format_to_fixpoint(source)
+
+    def test_corpus(self):
+        # The results for the scripts as they are don't change when formatted again.
+        for source in self.corpus:
+            zeekscript.format_to_fixpoint(source, max_iterations=2)
+
+    def test_mutations(self):
+        for idx in range(self.ITERATIONS):
+            source = self._mutate(self.rng.choice(self.corpus))
+            with self.subTest(seed=self.SEED, iteration=idx, source=source):
+                self._check(source)
+
+
+def test():
+    """Entry point for testing this module.
+
+    Returns True if successful, False otherwise.
+    """
+    res = unittest.main(sys.modules[__name__], verbosity=0, exit=False)
+    # This is how unittest.main() implements the exit code itself:
+    return res.result.wasSuccessful()
+
+if __name__ == '__main__':
+    sys.exit(not test())
@@ -0,0 +1,214 @@
+#! /usr/bin/env python
+import io
+import json
+import os
+import sys
+import unittest
+
+from os.path import join
+
+TESTS = os.path.dirname(os.path.realpath(__file__))
+ROOT = os.path.normpath(join(TESTS, '..'))
+
+# Prepend the tree's root folder to the module searchpath so we find zeekscript
+# via it. This allows tests to run without package installation. (We do need a
+# package build though, so the .so bindings library gets created.)
+sys.path.insert(0, ROOT)
+
+import zeekscript
+import zeekscript.lsp
+
+class TestLanguageServer(unittest.TestCase):
+    # These tests use ASCII only, so characters and UTF-16 code units agree.
+    NL = zeekscript.Formatter.NL.decode('UTF-8')
+    URI = 'untitled:test.zeek'
+
+    def _message(self, msg):
+        body = json.dumps(msg).encode('UTF-8')
+        return 'Content-Length: {}\r\n\r\n'.format(len(body)).encode('ascii') + body
+
+    def _request(self, msg_id, method, params=None):
+        return self._message({'jsonrpc': '2.0', 'id': msg_id, 'method': method,
+                              'params': params or {}})
+
+    def _notification(self, method, params=None):
+        return self._message({'jsonrpc': '2.0', 'method': method, 'params': params or {}})
+
+    def _open(self, text):
+        return self._notification('textDocument/didOpen', {
+            'textDocument': {'uri': self.URI, 'languageId': 'zeek', 'version': 1,
+                             'text': text.replace('\n', self.NL)}})
+
+    def _serve(self, *msgs):
+        # Runs a server on the given messages and returns its exit code and
+        # the responses it sent.
+        ostream = io.BytesIO()
+        server = zeekscript.LanguageServer(io.BytesIO(b''.join(msgs)), ostream,
+                                           zeekscript.FormatOptions())
+        ret = server.run()
+
+        responses = []
+        output = ostream.getvalue()
+        while output:
+            header, _, output = output.partition(b'\r\n\r\n')
+            length = int(header.split(b':')[1])
+            responses.append(json.loads(output[:length].decode('UTF-8')))
+            output = output[length:]
+
+        return ret, responses
+
+    def _result(self, *msgs):
+        _, responses = self._serve(*msgs)
+        self.assertEqual(len(responses), 1)
+        return responses[0]['result']
+
+    def test_initialize(self):
+        result = self._result(self._request(1, 'initialize'))
+        capabilities = result['capabilities']
+        self.assertTrue(capabilities['documentFormattingProvider'])
+        self.assertTrue(capabilities['documentRangeFormattingProvider'])
+        self.assertEqual(capabilities['documentOnTypeFormattingProvider'],
+                         {'firstTriggerCharacter': '}', 'moreTriggerCharacter': [';']})
+
+    def test_shutdown(self):
+        ret, responses = self._serve(self._request(1, 'shutdown'), self._notification('exit'),
+                                     self._request(2, 'shutdown'))
+        self.assertEqual(ret, 0)
+        self.assertEqual(responses, [{'jsonrpc': '2.0', 'id': 1, 'result': None}])
+
+    def test_request_after_shutdown(self):
+        ret, responses = self._serve(
+            self._open('global a = 1;\n'), self._request(1, 'shutdown'),
+            self._request(2, 'textDocument/formatting', {
+                'textDocument': {'uri': self.URI}, 'options': {}}),
+            self._notification('exit'))
+        self.assertEqual(ret, 0)
+        self.assertEqual(len(responses), 2)
+        self.assertEqual(responses[1]['id'], 2)
+        self.assertEqual(responses[1]['error']['code'], zeekscript.LSP_INVALID_REQUEST)
+
+    def test_client_response(self):
+        # Responses from the client get no answer.
+        _, responses = self._serve(
+            self._message({'jsonrpc': '2.0', 'id': 7, 'result': None}),
+            self._request(1, 'shutdown'))
+        self.assertEqual(responses, [{'jsonrpc': '2.0', 'id': 1, 'result': None}])
+
+    def test_exit_without_shutdown(self):
+        ret, responses = self._serve(self._notification('exit'))
+        self.assertEqual(ret, 1)
+        self.assertEqual(responses, [])
+
+    def test_unknown_method(self):
+        _, responses = self._serve(self._notification('workspace/didChangeConfiguration'),
+                                   self._request(1, 'textDocument/hover'))
+        self.assertEqual(len(responses), 1)
+        self.assertEqual(responses[0]['error']['code'], zeekscript.LSP_METHOD_NOT_FOUND)
+
+    def test_unknown_document(self):
+        _, responses = self._serve(self._request(1, 'textDocument/formatting', {
+            'textDocument': {'uri': self.URI}, 'options': {}}))
+        self.assertEqual(responses[0]['error']['code'], zeekscript.LSP_REQUEST_FAILED)
+
+    def test_formatting(self):
+        result = self._result(
+            self._open('global a = 1;\nglobal  b=2;\n'),
+            self._request(1, 'textDocument/formatting', {
+                'textDocument': {'uri': self.URI}, 'options': {}}))
+        self.assertEqual(result, [{
+            'range': {'start': {'line': 1, 'character': 0},
+                      'end': {'line': 2, 'character': 0}},
+            'newText': 'global b = 2;' + self.NL}])
+
+    def test_formatting_after_change(self):
+        result = self._result(
+            self._open('global a = 1;\n'),
+            self._notification('textDocument/didChange', {
+                'textDocument': {'uri': self.URI, 'version': 2},
+                'contentChanges': [{'text': 'global  a=1;' + self.NL}]}),
+            self._request(1, 'textDocument/formatting', {
+                'textDocument': {'uri': self.URI}, 'options': {}}))
+        self.assertEqual(result[0]['newText'], 'global a = 1;' + self.NL)
+
+    def test_range_formatting(self):
+        result = self._result(
+            self._open('global  a=1;\nglobal  b=2;\nglobal  c=3;\n'),
+            self._request(1, 'textDocument/rangeFormatting', {
+                'textDocument': {'uri': self.URI}, 'options': {},
+                'range': {'start': {'line': 1, 'character': 2},
+                          'end': {'line': 1, 'character': 4}}}))
+        # The edit covers the full line of the selected statement.
+        self.assertEqual(result, [{
+            'range': {'start': {'line': 1, 'character': 0},
+                      'end': {'line': 2, 'character': 0}},
+            'newText': 'global b = 2;' + self.NL}])
+
+    def test_on_type_formatting(self):
+        result = self._result(
+            self._open('event zeek_init()\n\t{\n\tprint  1;\n\tprint  2;\n\t}\n'),
+            self._request(1, 'textDocument/onTypeFormatting', {
+                'textDocument': {'uri': self.URI}, 'options': {},
+                'position': {'line': 2, 'character': 10}, 'ch': ';'}))
+        self.assertEqual(result, [{
+            'range': {'start': {'line': 2, 'character': 0},
+                      'end': {'line': 3, 'character': 0}},
+            'newText': '\tprint 1;' + self.NL}])
+
+    def test_on_type_formatting_incomplete(self):
+        # While the user types, parsing problems just mean there are no edits.
+        result = self._result(
+            self._open('event zeek_init()\n\t{\n\tprint  1;\n'),
+            self._request(1, 'textDocument/onTypeFormatting', {
+                'textDocument': {'uri': self.URI}, 'options': {},
+                'position': {'line': 2, 'character': 10}, 'ch': ';'}))
+        self.assertEqual(result, [])
+
+    def test_invalid_message(self):
+        body = b'{"jsonrpc": '
+        _, responses = self._serve(
+            'Content-Length: {}\r\n\r\n'.format(len(body)).encode('ascii') + body,
+            self._request(1, 'shutdown'))
+        self.assertEqual(responses[0]['error']['code'], zeekscript.LSP_PARSE_ERROR)
+        self.assertEqual(responses[1], {'jsonrpc': '2.0', 'id': 1, 'result': None})
+
+    def test_invalid_notification(self):
+        # A broken notification gets logged, and the server keeps serving.
+        _, responses = self._serve(
+            self._notification('textDocument/didOpen', {'textDocument': {'uri': self.URI}}),
+            self._request(1, 'shutdown'))
+        self.assertEqual(len(responses), 2)
+        self.assertEqual(responses[0]['method'], 'window/logMessage')
+        self.assertEqual(responses[0]['params']['type'], zeekscript.LSP_MESSAGE_ERROR)
+        self.assertIn('textDocument/didOpen', responses[0]['params']['message'])
+        self.assertEqual(responses[1], {'jsonrpc': '2.0', 'id': 1, 'result': None})
+
+
+class TestPositions(unittest.TestCase):
+    # "ü" takes two bytes in UTF-8 and one UTF-16 code unit, "😀" four bytes
+    # and two code units.
+    SOURCE = 'a\r\nü😀b\nc'.encode('UTF-8')
+
+    def test_offset(self):
+        for (line, character), offset in (
+                ((0, 0), 0), ((0, 1), 1), ((0, 5), 1), ((1, 1), 5),
+                ((1, 2), 5), ((1, 3), 9), ((1, 4), 10), ((2, 1), 12), ((3, 0), 12)):
+            self.assertEqual(zeekscript.lsp._offset(
+                self.SOURCE, {'line': line, 'character': character}), offset)
+
+    def test_position(self):
+        for offset, position in ((0, (0, 0)), (1, (0, 1)), (3, (1, 0)), (5, (1, 1)),
+                                 (9, (1, 3)), (11, (2, 0)), (12, (2, 1))):
+            self.assertEqual(zeekscript.lsp._position(self.SOURCE, offset), position)
+
+
+def test():
+    """Entry point for testing this module.
+
+    Returns True if successful, False otherwise.
+    """
+    res = unittest.main(sys.modules[__name__], verbosity=0, exit=False)
+    # This is how unittest.main() implements the exit code itself:
+    return res.result.wasSuccessful()
+
+if __name__ == '__main__':
+    sys.exit(not test())
@@ -1,10 +1,13 @@
 __version__ = "1.1.0-50"
-__all__ = ['cli', 'error', 'formatter', 'node', 'output', 'parser', 'script']
+__all__ = ['cli', 'error', 'formatter', 'lsp', 'node', 'options', 'output',
+           'parser', 'script']
 
 from .cli import *
 from .error import *
 from .formatter import *
+from .lsp import *
 from .node import *
+from .options import *
 from .output import *
 from .parser import *
 from .script import *
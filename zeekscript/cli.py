@@ -1,36 +1,77 @@
 """This module provides reusable command line parsers and tooling."""
 import argparse
+import collections
+import concurrent.futures
+import copy
 import io
+import itertools
 import os
 import sys
 import traceback
 
-from .error import Error, ParserError
-from .script import Script
+from .error import ConfigError, Error, FileError, ParserError
+from .lsp import LanguageServer
+from .options import FormatOptions, find_config_file
+from .script import (Script, _find_scripts, _is_formatted_as, _unified_diff,
+                     _write_atomically)
 from .output import print_error
 
 FILE_HELP = ('Use "-" to specify stdin as a filename. Omitting '
              'filenames entirely implies reading from stdin.')
 
+# Exit codes of zeek-format. 2 remains reserved for argparse's usage errors.
+EXIT_OK = 0
+EXIT_ERROR = 1 # Usage problems, parsing errors, and --check failures
+EXIT_FILE_ERROR = 3 # Scripts that could not be read or written
+EXIT_INTERNAL_ERROR = 4 # Formatter bugs
+
+# Per-script outcomes of zeek-format, as tallied in its summary.
+FORMAT_CHANGED = 'formatted'
+FORMAT_UNCHANGED = 'unchanged'
+FORMAT_FAILED = 'failed'
+
 def cmd_format(args):
     """This function implements Zeek script formatting for the command line.
 
     It determines input and output streams, parses each input into a Script
-    object, applies formattinge, and writes out the result.
-
-    Returns 0 in case of success, 1 in case of any errors -- this includes
-    formatter-internal errors as well as any problems encountered during
-    parsing. Encountered problems are written to stderr.
+    object, applies formattinge, and writes out the result. With --jobs, it
+    spreads the scripts across that many worker processes.
+
+    Returns EXIT_OK in case of success, EXIT_ERROR for usage mistakes and any
+    problems encountered during parsing, EXIT_FILE_ERROR when a script cannot
+    be read or written, and EXIT_INTERNAL_ERROR for formatter-internal errors.
+    When several of these occur, the highest code wins. Encountered problems
+    are written to stderr, prefixed by the script's name, and don't stop the
+    processing of remaining scripts. With --check, nothing gets written, and
+    the return value is also EXIT_ERROR when any script isn't already
+    formatted. With --diff, the output consists of unified diffs of the
+    changes formatting would make. Formatting options come from configuration
+    files, with command line flags taking precedence.
     """
-    if args.recursive and not args.inplace:
-        print_error('error: recursive file processing requires --inplace')
-        return 1
+    if args.inplace and (args.check or args.diff):
+        print_error('error: --inplace cannot be combined with --check or --diff')
+        return EXIT_ERROR
+
+    if args.recursive and not (args.inplace or args.check or args.diff):
+        print_error('error: recursive file processing requires --inplace, --check, or --diff')
+        return EXIT_ERROR
+
+    if args.jobs < 0:
+        print_error('error: --jobs requires a non-negative number')
+        return EXIT_ERROR
+
+    if args.line_length is not None and args.line_length < 1:
+        print_error('error: --line-length requires a positive number')
+        return EXIT_ERROR
 
     if not args.scripts:
         args.scripts = ['-']
 
     scripts = [] # Final list of Zeek scripts to format.
 
+    def report_ignored(path, reason):
+        print_error('skipping "{}", ignored via {}'.format(path, reason))
+
     for fname in args.scripts:
         if fname == '-':
             if args.inplace:
@@ -40,11 +81,8 @@ def cmd_format(args):
                 scripts.append(fname)
 
         elif os.path.isdir(fname):
-            if args.recursive: # implies --inplace
-                for dirpath, _, filenames in os.walk(fname):
-                    filenames = [n for n in filenames if n.endswith('.zeek')]
-                    filenames = [os.path.join(dirpath, n) for n in filenames]
-                    scripts.extend(filenames)
+            if args.recursive: # implies --inplace, --check, or --diff
+                scripts.extend(_find_scripts(fname, report_ignored if args.verbose else None))
             else:
                 print_error('warning: "{}" is a directory but --recursive not '
                             'set, skipping it'.format(fname))
@@ -53,59 +91,169 @@ def cmd_format(args):
             scripts.append(fname)
 
         else:
-            print_error('warning: skipping "{}"; not a supported file type')
+            print_error('warning: skipping "{}"; not a supported file type'.format(fname))
 
-    def do_write(source):
-        with open(ofname, 'wb') if ofname else sys.stdout.buffer as ostream:
-            ostream.write(source)
+    if len(scripts) > 1 and not (args.inplace or args.check or args.diff):
+        print_error('error: processing multiple files requires --inplace, --check, or --diff')
+        return EXIT_ERROR
 
-    if len(scripts) > 1 and not args.inplace:
-        print_error('error: processing multiple files requires --inplace')
-        return 1
+    try:
+        options = _find_options(scripts, args)
+    except ConfigError as err:
+        print_error('error: {}'.format(err))
+        return EXIT_ERROR
+
+    outcomes = collections.Counter() # Number of scripts per FORMAT_* outcome
+    ret = EXIT_OK
+
+    def do_format():
+        jobs = args.jobs or os.cpu_count() or 1
+        work = list(zip(scripts, options,
+                        itertools.repeat(args.inplace), itertools.repeat(args.check),
+                        itertools.repeat(args.diff)))
+
+        if jobs > 1 and len(scripts) > 1:
+            with concurrent.futures.ProcessPoolExecutor(max_workers=jobs) as pool:
+                # Worker processes read stdin from /dev/null, so stdin gets
+                # formatted right here. The results keep the scripts' order.
+                futures = [None if item[0] == '-' else pool.submit(_format_script, *item)
+                           for item in work]
+                for item, future in zip(work, futures):
+                    yield _format_script(*item) if future is None else future.result()
+        else:
+            yield from itertools.starmap(_format_script, work)
 
-    errs = 0
+    for outcome, code, msgs, output in do_format():
+        outcomes[outcome] += 1
+        ret = max(ret, code)
 
-    for fname in scripts:
-        script = Script(fname)
-        ofname = fname if args.inplace else None
-
-        try:
-            if not script.parse():
-                errs += 1
-                _, _, msg = script.get_error()
-                if len(scripts) > 1:
-                    print_error('{}: {}'.format(fname, msg))
-                else:
-                    print_error(msg)
-        except Error as err:
-            print_error('parsing error: ' + str(err))
-            do_write(script.source)
-            return 1
-        except Exception as err:
-            print_error('internal error: ' + str(err))
-            traceback.print_exc(file=sys.stderr)
-            do_write(script.source)
-            return 1
-
-        buf = io.BytesIO()
-
-        try:
-            script.format(buf, not args.no_linebreaks)
-        except Exception as err:
-            print_error('internal error: ' + str(err))
-            traceback.print_exc(file=sys.stderr)
-            do_write(script.source)
-            return 1
-
-        # Write out the complete, reformatted source.
-        do_write(buf.getvalue())
+        for msg in msgs:
+            print_error(msg)
+
+        if output is not None:
+            sys.stdout.buffer.write(output)
 
     if args.inplace:
-        print('{} file{} processed, {} error{}'.format(
+        print('{} file{} processed: {} formatted, {} unchanged, {} failed'.format(
             len(scripts), '' if len(scripts) == 1 else 's',
-            errs, '' if errs == 1 else 's'))
+            outcomes[FORMAT_CHANGED], outcomes[FORMAT_UNCHANGED],
+            outcomes[FORMAT_FAILED]))
+
+    return ret
+
+
+def _find_options(scripts, args):
+    """Returns the list of FormatOptions to use for the given scripts.
+
+    Each script gets the options from the closest .zeek-format.toml or
+    zeekscript.toml file in its directory or above. For stdin, that's the
+    directory of --stdin-filepath, or the current one. With --editorconfig,
+    settings from .editorconfig files apply first, and the configuration
+    file's options override them. Formatting flags given on the command line
+    override both. Raises zeekscript.ConfigError when a configuration file is
+    broken.
+    """
+    configs = {} # (config file path, base options) -> FormatOptions they yield
+    result = []
+
+    for fname in scripts:
+        # For stdin, resolve settings as for a script at the given path, or
+        # in the current directory.
+        path = fname
+        if fname == '-':
+            path = args.stdin_filepath or os.path.join(os.getcwd(), 'stdin.zeek')
+        base = FormatOptions.from_editorconfig(path) if args.editorconfig else FormatOptions()
+        config = find_config_file(path)
+
+        if config is None:
+            options = base
+        else:
+            key = (config, tuple(sorted(vars(base).items())))
+            if key not in configs:
+                configs[key] = FormatOptions.from_file(config, base)
+            options = copy.copy(configs[key])
+
+        if args.no_linebreaks:
+            options.enable_linebreaks = False
+        if args.line_length is not None:
+            options.max_line_len = args.line_length
+
+        result.append(options)
 
-    return int(errs > 0)
+    return result
+
+
+def _format_script(fname, options, inplace, check, diff):
+    """Formats a single script for cmd_format().
+
+    This may run in a worker process, so it returns its results instead of
+    reporting them: a tuple of the FORMAT_* outcome, an exit code, a list of
+    messages for stderr, and the bytes to write to stdout, or None if there
+    are none.
+    """
+    name = 'stdin' if fname == '-' else fname
+    script = Script(fname)
+    msgs = []
+
+    # Whether we write the script to stdout, even in case of errors.
+    to_stdout = not (inplace or check or diff)
+
+    def internal_error(err):
+        return (FORMAT_FAILED, EXIT_INTERNAL_ERROR,
+                ['{}: internal error: {}'.format(name, err),
+                 traceback.format_exc().rstrip()],
+                script.get_original_content() if to_stdout else None)
+
+    try:
+        if not script.parse():
+            _, _, msg = script.get_error()
+            msgs.append('{}: {}'.format(name, msg))
+    except FileError as err:
+        return FORMAT_FAILED, EXIT_FILE_ERROR, ['{}: error: {}'.format(name, err)], None
+    except Error as err:
+        return (FORMAT_FAILED, EXIT_ERROR, ['{}: parsing error: {}'.format(name, err)],
+                script.get_original_content() if to_stdout else None)
+    except Exception as err: # pylint: disable=broad-except
+        return internal_error(err)
+
+    buf = io.BytesIO()
+
+    try:
+        script.format(buf, options=options)
+    except Exception as err: # pylint: disable=broad-except
+        return internal_error(err)
+
+    result = buf.getvalue()
+    code = EXIT_ERROR if msgs else EXIT_OK
+
+    if msgs:
+        outcome = FORMAT_FAILED
+    elif result == script.get_original_content():
+        outcome = FORMAT_UNCHANGED
+    else:
+        outcome = FORMAT_CHANGED
+
+    if check or diff:
+        output = None
+        if check and not _is_formatted_as(script.get_original_content(), result):
+            code = EXIT_ERROR
+            msgs.append('{} is not formatted'.format(
+                'stdin' if fname == '-' else '"{}"'.format(fname)))
+        if diff:
+            output = _unified_diff(script.get_original_content(), result, name)
+            output = output.encode('UTF-8') if output else None
+        return outcome, code, msgs, output
+
+    if inplace:
+        if result != script.get_original_content():
+            try:
+                _write_atomically(fname, result)
+            except FileError as err:
+                msgs.append('{}: error: {}'.format(name, err))
+                return FORMAT_FAILED, EXIT_FILE_ERROR, msgs, None
+        return outcome, code, msgs, None
+
+    return outcome, code, msgs, result
 
 
 def cmd_parse(args):
@@ -139,6 +287,16 @@ def cmd_parse(args):
     return 0
 
 
+def cmd_lsp(_args):
+    """This function runs a Zeek script language server for the command line.
+
+    The server speaks the Language Server Protocol over stdin and stdout
+    until the client exits. Returns 0 when the client shut it down properly,
+    and 1 otherwise.
+    """
+    return LanguageServer(sys.stdin.buffer, sys.stdout.buffer).run()
+
+
 def add_version_arg(parser):
     parser.add_argument(
         '--version', '-v', action='store_true', help='show version and exit')
@@ -150,12 +308,40 @@ def add_format_cmd(parser):
     default."""
     parser.set_defaults(run_cmd=cmd_format)
     parser.add_argument(
-        '--inplace', '-i', action='store_true',
+        '--inplace', '--in-place', '-i', action='store_true',
         help='change provided files instead of writing to stdout')
     parser.add_argument(
         '--recursive', '-r', action='store_true',
         help='process *.zeek files recursively when provided directories '
-        'instead of files. Requires --inplace.')
+        'instead of files. Requires --inplace, --check, or --diff.')
+    parser.add_argument(
+        '--verbose', action='store_true',
+        help='report additional detail, such as the scripts that --recursive '
+        'skips because of .zeekformatignore files')
+    parser.add_argument(
+        '--check', action='store_true',
+        help='only verify that provided files are already formatted, '
+        'reporting any that are not and exiting non-zero. Writes nothing.')
+    parser.add_argument(
+        '--diff', action='store_true',
+        help='show unified diffs of the changes formatting would make, '
+        'instead of the formatted scripts. Writes nothing.')
+    parser.add_argument(
+        '--jobs', '-j', metavar='N', type=int, default=1,
+        help='format up to N scripts in parallel. 0 means one per CPU. '
+        'Default: 1.')
+    parser.add_argument(
+        '--line-length', '-l', metavar='N', type=int,
+        help='wrap lines that grow longer than N characters. Default: {}, '
+        'unless configured otherwise.'.format(FormatOptions().max_line_len))
+    parser.add_argument(
+        '--editorconfig', action='store_true',
+        help='take indentation, line ending, and final newline settings from '
+        '.editorconfig files. Configuration files and flags override them.')
+    parser.add_argument(
+        '--stdin-filepath', metavar='PATH',
+        help='take configuration for the script read from stdin as if it '
+        'were located at PATH, which need not exist')
     parser.add_argument(
         '--no-linebreaks', action='store_true', help=argparse.SUPPRESS)
     parser.add_argument(
@@ -173,3 +359,10 @@ def add_parse_cmd(parser):
     parser.add_argument(
         'script', metavar='FILE', nargs='?',
         help='Zeek script to parse. ' + FILE_HELP)
+
+
+def add_lsp_cmd(parser):
+    """This adds a Zeek script language server CLI interface to the given
+    argparse parser. It registers the cmd_lsp() callback as the parser's
+    run_cmd default."""
+    parser.set_defaults(run_cmd=cmd_lsp)
@@ -1,4 +1,5 @@
 """Exception hierarchy for the zeekscript package."""
+import difflib
 
 class Error(Exception):
     """Base class for all zeekscript errors."""
@@ -6,5 +7,96 @@ class Error(Exception):
 class FileError(Error):
     """System errors while processing script files"""
 
+class ConfigError(Error):
+    """Problems with formatting configuration, such as unknown options"""
+
 class ParserError(Error):
-    """A hard parsing error, producing no parse tree."""
+    """A hard parsing error, producing no parse tree, or a parse tree with
+    errors when the caller does not tolerate those."""
+    def __init__(self, msg, diagnostics=None, context=None):
+        super().__init__(msg)
+        # A list of zeekscript.Diagnostic instances, one per problem in the
+        # script, in order of appearance. Empty when we have no specifics.
+        self.diagnostics = diagnostics or []
+        # The offending script content of the first problem, as a string.
+        self.context = context
+
+    @property
+    def line(self):
+        """The 0-based line of the first problem in the script, or None."""
+        return self.diagnostics[0].start_row if self.diagnostics else None
+
+    @property
+    def column(self):
+        """The 0-based column of the first problem in the script, or None."""
+        return self.diagnostics[0].start_column if self.diagnostics else None
+
+
+class IdempotencyError(Error):
+    """Formatting a script's formatted version changed it further.
+
+    This indicates a formatter bug. pass1 and pass2 hold the results of the
+    two formatting passes, as bytes, and diff() shows how they differ. When
+    the error stems from zeekscript.format_to_fixpoint(), iterations is the
+    number of passes made without reaching a stable result, pass1 and pass2
+    are the last two of them, and the message says how many passes it took.
+    """
+    def __init__(self, pass1, pass2, iterations=None):
+        if iterations is None:
+            super().__init__('formatting is not idempotent')
+        else:
+            super().__init__('formatting did not stabilize after {} passes'.format(iterations))
+        self.pass1 = pass1
+        self.pass2 = pass2
+        self.iterations = iterations
+
+    def __reduce__(self):
+        return (self.__class__, (self.pass1, self.pass2, self.iterations))
+
+    def diff(self):
+        """Returns a unified diff from the first to the second pass, as string."""
+        lines1 = self.pass1.decode('UTF-8', errors='replace').splitlines(keepends=True)
+        lines2 = self.pass2.decode('UTF-8', errors='replace').splitlines(keepends=True)
+        return ''.join(difflib.unified_diff(lines1, lines2, 'pass1', 'pass2'))
+
+
+class EncodingError(Error):
+    """Script content that isn't valid UTF-8 where text is required, such as
+    when writing to a text stream. offset is the position of the first
+    offending byte in the content."""
+    def __init__(self, offset):
+        super().__init__('invalid UTF-8 at byte offset {}'.format(offset))
+        self.offset = offset
+
+    def __reduce__(self):
+        return (self.__class__, (self.offset,))
+
+
+class Diagnostic:
+    """A single problem in a script, with its location.
+
+    Byte offsets are into the script's content and work like slice indices, so
+    start_byte and end_byte delineate the problematic content. start_row and
+    start_column are 0-based, with the column counted in bytes, as TreeSitter
+    does. kind is the type of the offending parse tree node: "ERROR" for
+    content the parser couldn't make sense of, or the grammar symbol that is
+    missing or erroneous.
+    """
+    def __init__(self, message, start_byte, end_byte, start_row, start_column,
+                 kind=None):
+        self.message = message
+        self.start_byte = start_byte
+        self.end_byte = end_byte
+        self.start_row = start_row
+        self.start_column = start_column
+        self.kind = kind
+
+    def __eq__(self, other):
+        if not isinstance(other, Diagnostic):
+            return NotImplemented
+        return vars(self) == vars(other)
+
+    def __repr__(self):
+        return 'Diagnostic({!r}, {}, {}, {}, {}, {!r})'.format(
+            self.message, self.start_byte, self.end_byte,
+            self.start_row, self.start_column, self.kind)
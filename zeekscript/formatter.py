@@ -14,11 +14,15 @@ comments). The CST features such elements, whereas the AST does not. You can
 examine the difference by playing with `zeek-script parse ...` vs `zeek-script
 parse --concrete`.
 """
+import copy
 import enum
 import inspect
+import io
 import os
 import sys
 
+from .options import BraceStyle, HexCase, OperatorPosition, SwitchCaseIndent
+
 class NodeMapper:
     """Maps symbol names in the TS grammar (e.g "module_decl") to formatter classes."""
     def __init__(self):
@@ -75,10 +79,14 @@ class Hint(enum.Flag):
     """
     NONE = enum.auto()
     GOOD_AFTER_LB = enum.auto() # A linebreak before this item is encouraged.
+    GOOD_BEFORE_LB = enum.auto() # A linebreak after this item is encouraged.
     NO_LB_BEFORE = enum.auto() # Never line-break before this item.
     NO_LB_AFTER = enum.auto() # Never line-break after this item.
     ZERO_WIDTH = enum.auto() # This item doesn't contribute to line length.
     COMPLEX_BLOCK = enum.auto() # A {}-block is complex enough to linebreak
+    ONE_PER_LINE = enum.auto() # A list puts each element on a line of its own
+    TRAILING_COMMA = enum.auto() # A one-per-line list ends with a comma, too
+    KEEP_TOGETHER = enum.auto() # No line breaks anywhere in this subtree.
 
 
 class Formatter:
@@ -127,6 +135,10 @@ class Formatter:
             return None
 
     def _format_child_impl(self, node, indent, hints=None):
+        # Keeping an item on one line applies to everything inside it.
+        if Hint.KEEP_TOGETHER in self.hints:
+            hints = (hints or Hint.NONE) | Hint.KEEP_TOGETHER
+
         fclass = Formatter.lookup(node)
         formatter = fclass(self.script, node, self.ostream,
                            indent=self.indent + int(indent),
@@ -216,9 +228,16 @@ class Formatter:
 
         The statement list is optional, and we need to take into account
         comments to tweak the layout to "{ }" if there's really nothing between
-        the braces. The need to indent depends on the caller's context.
+        the braces. The need to indent depends on the caller's context, but
+        only for Whitesmiths-style braces. With other brace styles, the braces
+        stay at our level and the statements get indented.
         """
-        self._format_child(indent=indent, hints=Hint.NO_LB_BEFORE) # '{'
+        if self.ostream.options.brace_style != BraceStyle.WHITESMITHS:
+            brace_indent, stmt_indent = False, True
+        else:
+            brace_indent, stmt_indent = indent, indent
+
+        self._format_child(indent=brace_indent, hints=Hint.NO_LB_BEFORE) # '{'
 
         # Shorten braces to "{ }" if there is at most whitespace between them.
         if (self._get_child_token() == '}' and
@@ -230,9 +249,34 @@ class Formatter:
         self._write_nl()
 
         if self._get_child_type() == 'stmt_list':
-            self._format_child(indent=indent) # <stmt_list>
+            self._format_child(indent=stmt_indent) # <stmt_list>
+            self._write_nl()
+        self._format_child(indent=brace_indent) # '}'
+
+    def _write_brace_break(self):
+        """Writes what precedes the opening brace of a block: a newline, or a
+        space when the brace style attaches the brace to the current line."""
+        if self.ostream.options.brace_style == BraceStyle.ATTACH:
+            self._write_sp()
+        else:
             self._write_nl()
-        self._format_child(indent=indent) # '}'
+
+    def _render_one_line(self):
+        """Returns this node formatted without any line breaks.
+
+        The result has no surrounding whitespace, but may contain newlines when
+        the node includes comments.
+        """
+        options = copy.copy(self.ostream.options)
+        options.enable_linebreaks = False
+
+        buf = io.BytesIO()
+        formatter = self.node.formatter
+        with type(self.ostream)(buf, options=options) as ostream:
+            Formatter.lookup(self.node)(self.script, self.node, ostream, self.indent).format()
+        self.node.formatter = formatter # Rendering hooked in another one
+
+        return buf.getvalue().strip()
 
     def _write(self, data, raw=False):
         if isinstance(data, str):
@@ -258,6 +302,11 @@ class Formatter:
     def _write_sp(self, num=1):
         self._write(b' ' * num)
 
+    def _write_bracket_sp(self):
+        # The space padding the inside of index brackets, if configured.
+        if self.ostream.options.bracket_spacing:
+            self._write_sp()
+
     def _write_nl(self, num=1, force=False, is_midline=False):
         # It's rare that we really want to write newlines multiple times in
         # a row. If we just wrote one, don't do so again unless forced.
@@ -430,9 +479,10 @@ class SpaceSeparatedFormatter(Formatter):
 
 
 class PreprocDirectiveFormatter(LineFormatter):
-    """@if and friends don't get indented or line-broken."""
+    """@if and friends don't get line-broken, and by default not indented."""
     def format(self):
-        self.ostream.use_tab_indent(False)
+        indent = self.ostream.options.indent_preproc_directives
+        self.ostream.use_tab_indent(indent)
         self.ostream.use_linebreaks(False)
         super().format()
         self.ostream.use_tab_indent(True)
@@ -448,17 +498,55 @@ class ModuleDeclFormatter(Formatter):
 
 
 class ExportDeclFormatter(Formatter):
+    """Export blocks have a fixed layout: "{" on the line of the "export",
+    one indentation level for the declarations, and the closing "}" on a line
+    of its own, unindented. Blank lines never follow the "{" or precede the
+    "}", while one always separates the block from what comes after it. Empty
+    blocks without comments become "export { }".
+    """
     def format(self):
         # No Whitesmith here: "{" on same line, closing "}" unindented.
         self._format_child() # 'export'
         self._write_sp()
-        self._format_child(hints=Hint.NO_LB_BEFORE) # '{'
-        self._write_nl()
-        while self._get_child_name() == 'decl':
-            self._format_child(indent=True)
-        self._format_child() # '}'
+
+        lbrace, rbrace = self._get_child(), self._get_child(offset=1)
+
+        if rbrace is not None and rbrace.token() == '}' and not any(
+                node.is_comment() for node in lbrace.next_cst_siblings + rbrace.prev_cst_siblings):
+            self._format_child(hints=Hint.NO_LB_BEFORE) # '{'
+            self._write_sp()
+            self._format_child(hints=Hint.NO_LB_BEFORE) # '}'
+        else:
+            self._format_child(hints=Hint.NO_LB_BEFORE) # '{'
+            self._write_nl()
+            while self._get_child_name() == 'decl':
+                self._format_child(indent=True)
+            self._format_child() # '}'
+
         self._write_nl()
 
+        if self._needs_blank_line():
+            self._write_nl(force=True)
+
+    def _needs_blank_line(self):
+        """Returns True if something follows the block without a blank line
+        in between, in which case we add one. Blank lines in the input are
+        already preserved as usual.
+        """
+        decl = self.node.parent
+        nxt = decl.next_sibling if decl else None
+
+        if nxt is None:
+            return False
+
+        # Comments trailing the "}" don't count, but ones on subsequent lines
+        # do, since they belong to what follows.
+        end_row = decl.end_point[0]
+        rows = [node.start_point[0] for node in nxt.prev_cst_siblings + [nxt]
+                if not node.is_nl() and node.start_point[0] > end_row]
+
+        return bool(rows) and rows[0] == end_row + 1
+
 
 class TypedInitializerFormatter(Formatter):
     """Helper for common construct that's not a separate symbol in the grammar:
@@ -614,6 +702,40 @@ class EnumBodyFormatterMixin(ComplexBlockFormatterMixin):
         self._format_child() # '}'
 
 
+class SignatureFormatterMixin():
+    """A mixin for the parameter lists of functions, events, and hooks."""
+    def _is_long_signature(self):
+        """Predicate, returns true if the remaining signature should put its
+        parameters on individual lines.
+
+        That's the case when the expand_long_signatures option is on and this
+        node, rendered on a single line, doesn't fit the current one.
+        """
+        options = self.ostream.options
+
+        # Without linebreaks, there's no expanding. This also covers our own
+        # single-line rendering below.
+        if not (options.expand_long_signatures and options.enable_linebreaks):
+            return False
+
+        if Hint.KEEP_TOGETHER in self.hints:
+            return False
+
+        rendered = self._render_one_line()
+        return self.NL in rendered or not self.ostream.fits(len(rendered))
+
+    def _format_paren_formal_args(self, expand):
+        """Formats a '(' <formal_args>? ')' sequence."""
+        self._format_child(hints=Hint.NO_LB_BEFORE) # '('
+        if self._get_child_name() == 'formal_args':
+            if expand:
+                self._write_nl()
+                self._format_child(hints=Hint.ONE_PER_LINE) # <formal_args>
+            else:
+                self._format_child() # <formal_args>
+        self._format_child(hints=Hint.NO_LB_BEFORE) # ')'
+
+
 class RedefEnumDeclFormatter(Formatter, EnumBodyFormatterMixin):
     def format(self):
         self._format_child() # 'redef'
@@ -665,7 +787,8 @@ class TypeDeclFormatter(Formatter):
         self._write_nl()
 
 
-class TypeFormatter(SpaceSeparatedFormatter, EnumBodyFormatterMixin):
+class TypeFormatter(SpaceSeparatedFormatter, EnumBodyFormatterMixin,
+                    SignatureFormatterMixin):
     def format(self):
         if self._get_child_token() == 'set':
             self._format_child() # 'set'
@@ -704,11 +827,9 @@ class TypeFormatter(SpaceSeparatedFormatter, EnumBodyFormatterMixin):
             self._format_child_range(2) # 'function' <func_params>
 
         elif self._get_child_token() in ['event', 'hook']:
+            expand = self._is_long_signature() # Must call before we consume
             self._format_child() # 'event'/'hook'
-            self._format_child(hints=Hint.NO_LB_BEFORE) # '('
-            if self._get_child_name() == 'formal_args':
-                self._format_child()
-            self._format_child(hints=Hint.NO_LB_BEFORE) # ')'
+            self._format_paren_formal_args(expand)
 
         else:
             # Format anything else with plain space separation, e.g. "vector of foo"
@@ -791,12 +912,9 @@ class FuncHdrVariantFormatter(Formatter):
             self._format_child() # <attr_list>
 
 
-class FuncParamsFormatter(Formatter):
+class FuncParamsFormatter(Formatter, SignatureFormatterMixin):
     def format(self):
-        self._format_child(hints=Hint.NO_LB_BEFORE) # '('
-        if self._get_child_name() == 'formal_args':
-            self._format_child() # <formal_args>
-        self._format_child(hints=Hint.NO_LB_BEFORE) # ')'
+        self._format_paren_formal_args(self._is_long_signature())
         if self._get_child_token() == ':':
             self._format_child(hints=Hint.NO_LB_AFTER) # ':'
             self._write_sp()
@@ -805,12 +923,69 @@ class FuncParamsFormatter(Formatter):
 
 class FuncBodyFormatter(Formatter):
     def format(self):
-        self._write_nl()
+        if self.ostream.options.collapse_short_functions:
+            body = self._render_one_liner()
+            if body is not None:
+                self._write(b'{ ' + body + b' }')
+                return
+
+        self._write_brace_break()
         self._format_curly_statement_list()
 
+    def _render_one_liner(self):
+        """Renders an anonymous function's body for single-line layout.
+
+        This applies to bodies of function expressions, such as in
+        &default=function(i: count): string { return fmt("%s", i); }, that
+        consist of a single, comment-free statement. Returns the statement
+        formatted without linebreaks, or None if the body doesn't qualify or
+        the result wouldn't fit the current line.
+        """
+        if not self.node.parent or self.node.parent.type != 'expr':
+            return None
+
+        # '{' <stmt_list> '}', with exactly one statement in the list.
+        kids = self.node.nonerr_children
+        if len(kids) != 3 or kids[1].type != 'stmt_list':
+            return None
+        if len(kids[1].nonerr_children) != 1:
+            return None
+
+        for node, _ in self.node.traverse(include_cst=True):
+            if node.is_comment() or node.is_error() or node.is_missing:
+                return None
+            if len(node.children) != len(node.nonerr_children):
+                return None
+
+        options = copy.copy(self.ostream.options)
+        options.enable_linebreaks = False
+
+        buf = io.BytesIO()
+        with type(self.ostream)(buf, options=options) as ostream:
+            stmt = kids[1].nonerr_children[0]
+            Formatter.lookup(stmt)(self.script, stmt, ostream).format()
+
+        body = buf.getvalue().strip()
+
+        # Statements that need several lines, such as if-statements, stay
+        # expanded.
+        if self.NL in body or not self.ostream.fits(len(body) + 4):
+            return None
+
+        return body
+
 
 class FormalArgsFormatter(Formatter):
     def format(self):
+        if Hint.ONE_PER_LINE in self.hints:
+            # Every parameter goes on a line of its own, in one piece.
+            while self._get_child_name() == 'formal_arg':
+                self._format_child(indent=True, hints=Hint.KEEP_TOGETHER) # <formal_arg>
+                if self._get_child():
+                    self._format_child(hints=Hint.NO_LB_BEFORE) # ',' or ';'
+                    self._write_nl()
+            return
+
         while self._get_child_name() == 'formal_arg':
             self._format_child() # <formal_arg>
             if self._get_child():
@@ -832,10 +1007,12 @@ class FormalArgFormatter(Formatter):
 class IndexSliceFormatter(Formatter):
     def format(self):
         self._format_child(hints=Hint.NO_LB_BEFORE) # '['
+        self._write_bracket_sp()
         while self._get_child_token() != ']':
             self._format_child()
             if self._get_child_token() != ']':
                 self._write_sp()
+        self._write_bracket_sp()
         self._format_child(hints=Hint.NO_LB_BEFORE) # ']'
 
 
@@ -856,9 +1033,24 @@ class StmtFormatter(TypedInitializerFormatter):
 
         This may either be an { ... } block or a single-line statement.
         """
-        self._write_nl()
-        self._format_child(indent=True) # <stmt>
-        self._write_nl()
+        is_block = self._get_child().has_property(
+            lambda n: n.nonerr_children[0].token() == '{')
+        style = self.ostream.options.brace_style
+
+        if not is_block or style == BraceStyle.WHITESMITHS:
+            self._write_nl()
+            self._format_child(indent=True) # <stmt>
+            self._write_nl()
+            return
+
+        # The block's braces go at our level, so no indentation here. With
+        # attached braces, an else-branch follows the closing brace directly.
+        self._write_brace_break()
+        self._format_child() # <stmt>
+        if style == BraceStyle.ATTACH and self._get_child_token() == 'else':
+            self._write_sp()
+        else:
+            self._write_nl()
 
     def _format_when(self):
         self._format_child() # 'when'
@@ -877,7 +1069,7 @@ class StmtFormatter(TypedInitializerFormatter):
             self._format_child() # 'timeout'
             self._write_sp()
             self._format_child() # <expr>
-            self._write_nl()
+            self._write_brace_break()
             self._format_curly_statement_list() # '{' <stmt_list> '}'
             self._write_nl()
 
@@ -889,6 +1081,7 @@ class StmtFormatter(TypedInitializerFormatter):
         if start_token == '{':
             # We don't have to do anything re. Whitesmith here: if this needs
             # to be indented, the caller has already ensured so via indent=True.
+            # Other brace styles indent only the statements, see below.
             self._format_curly_statement_list(indent=False) # '{' <stmt_list> '}'
 
         elif start_token in ['print', 'event']:
@@ -944,8 +1137,9 @@ class StmtFormatter(TypedInitializerFormatter):
             self._format_child() # 'switch'
             self._write_sp()
             self._format_child() # <expr>
-            self._write_nl()
-            self._format_child(indent=True) # '{'
+            self._write_brace_break()
+            whitesmiths = self.ostream.options.brace_style == BraceStyle.WHITESMITHS
+            self._format_child(indent=whitesmiths) # '{'
             # Shorten braces to "{ }" if there is at most whitespace between them.
             if (self._get_child_token() == '}' and
                 self._get_child().has_only_whitespace_before()):
@@ -954,9 +1148,10 @@ class StmtFormatter(TypedInitializerFormatter):
             else:
                 if self._get_child_name() == 'case_list':
                     self._write_nl()
-                    self._format_child(indent=True) # <case_list>
+                    self._format_child(indent=self.ostream.options.switch_case_indent ==
+                                       SwitchCaseIndent.INDENTED) # <case_list>
                 self._write_nl()
-                self._format_child(indent=True) # '}'
+                self._format_child(indent=whitesmiths) # '}'
             self._write_nl()
 
         elif start_token == 'for':
@@ -1055,7 +1250,18 @@ class StmtFormatter(TypedInitializerFormatter):
 
 class ExprListFormatter(Formatter, ComplexSequenceFormatterMixin):
     def format(self):
-        if self.is_complex():
+        if Hint.ONE_PER_LINE in self.hints:
+            # Every element gets a line of its own. With a trailing comma, so
+            # does what follows the list.
+            while self._get_child_name() == 'expr':
+                self._format_child(indent=True) # <expr>
+                if self._get_child():
+                    self._format_child(hints=Hint.NO_LB_BEFORE) # ','
+                    self._write_nl()
+                elif Hint.TRAILING_COMMA in self.hints:
+                    self._write(',')
+                    self._write_nl()
+        elif self.is_complex():
             while self._get_child_name() == 'expr':
                 self._format_child(indent=True) # <expr>
                 if self._get_child():
@@ -1118,6 +1324,21 @@ class ExprFormatter(SpaceSeparatedFormatter):
         return (len(self.node.nonerr_children) == 3 and
                 self._get_child_token(offset=1, absolute=True) in ('||', '&&'))
 
+    def _is_ternary(self):
+        """Predicate, returns true if this a <cond> ? <expr> : <expr> expression."""
+        return (len(self.node.nonerr_children) == 5 and
+                self._get_child_token(offset=1, absolute=True) == '?' and
+                self._get_child_token(offset=3, absolute=True) == ':')
+
+    def _is_long_ternary(self):
+        """Predicate, returns true if this is a conditional expression that
+        doesn't fit on the current line, when rendered on a single one."""
+        if not self.ostream.options.enable_linebreaks or Hint.KEEP_TOGETHER in self.hints:
+            return False
+
+        rendered = self._render_one_line()
+        return self.NL in rendered or not self.ostream.fits(len(rendered))
+
     def _is_string_concat(self):
         """Predicate, returns true if this a <string> + <string> expression."""
         def is_constant_expr(node):
@@ -1152,6 +1373,81 @@ class ExprFormatter(SpaceSeparatedFormatter):
 
         return node and not isinstance(node.formatter, ExprFormatter)
 
+    def _is_long_constructor(self):
+        """Predicate, returns true if this is a set(), vector(), or table()
+        constructor with elements that should go on individual lines.
+
+        That's the case when the expand_long_constructors option is on and the
+        constructor, rendered on a single line, doesn't fit the current one.
+        """
+        options = self.ostream.options
+
+        # Without linebreaks, there's no expanding. This also covers our own
+        # single-line rendering below.
+        if not (options.expand_long_constructors and options.enable_linebreaks):
+            return False
+
+        if (self._get_child_token() not in ('set', 'vector', 'table') or
+                self._get_child_name(offset=2) != 'expr_list'):
+            return False
+
+        rendered = self._render_one_line()
+        return self.NL in rendered or not self.ostream.fits(len(rendered))
+
+    def _is_expandable_call(self):
+        """Predicate, returns true if this is a function call with arguments
+        that may go on individual lines, via the expand_long_calls option.
+        """
+        options = self.ostream.options
+
+        if not (options.expand_long_calls and options.enable_linebreaks):
+            return False
+
+        if Hint.KEEP_TOGETHER in self.hints:
+            return False
+
+        return (self._get_child_name() == 'expr' and self._get_child_token(offset=1) == '(' and
+                self._get_child_name(offset=2) == 'expr_list')
+
+    def _is_long_call(self):
+        """Predicate, returns true if this is a function call with arguments
+        that should go on individual lines.
+
+        That's the case when the call is expandable and, rendered on a single
+        line, doesn't fit the current one.
+        """
+        if not self._is_expandable_call():
+            return False
+
+        rendered = self._render_one_line()
+        return self.NL in rendered or not self.ostream.fits(len(rendered))
+
+    def _is_short_record(self):
+        """Predicate, returns true if this is a [...] constructor that should
+        stay on a single line.
+
+        That's the case when the keep_short_records option is on and the
+        constructor's formatted width, regardless of its layout in the input,
+        stays within the maximum line length at the current indentation.
+        """
+        options = self.ostream.options
+
+        if not (options.keep_short_records and options.enable_linebreaks):
+            return False
+
+        if Hint.KEEP_TOGETHER in self.hints:
+            return False # An enclosing constructor already covers us.
+
+        rendered = self._render_one_line()
+        return self.NL not in rendered and self.ostream.fits_own_line(len(rendered))
+
+    def _is_record_constructor(self):
+        """Predicate, returns true if this is a [...] constructor of a record,
+        as opposed to a table key or index, i.e. one that starts with a
+        $field=value assignment."""
+        first = self.node.nonerr_children[1].nonerr_children[0]
+        return first.name() == 'expr' and first.nonerr_children[0].token() == '$'
+
     def format(self):
         cn1, cn2, cn3 = [self._get_child_name(offset=n) for n in (0,1,2)]
         ct1, ct2, ct3 = [self._get_child_token(offset=n) for n in (0,1,2)]
@@ -1159,7 +1455,9 @@ class ExprFormatter(SpaceSeparatedFormatter):
         if cn1 == 'expr' and ct2 == '[':
             self._format_child() # <expr>
             self._format_child(hints=Hint.NO_LB_BEFORE | Hint.NO_LB_AFTER) # '['
+            self._write_bracket_sp()
             self._format_child() # <expr_list>
+            self._write_bracket_sp()
             self._format_child(hints=Hint.NO_LB_BEFORE) # ']'
 
         elif cn1 == 'expr' and ct2 == '$':
@@ -1193,9 +1491,17 @@ class ExprFormatter(SpaceSeparatedFormatter):
             self._format_child() # <expr>
 
         elif ct1 == '[':
-            self._format_child(hints=Hint.NO_LB_BEFORE) # '['
+            # When keeping the constructor together, its closing ']' may still
+            # get followed by a line break.
+            hints = Hint.KEEP_TOGETHER if self._is_short_record() else Hint.NONE
+            self._format_child(hints=Hint.NO_LB_BEFORE | hints) # '['
             if self._get_child_name() == 'expr_list':
-                self._format_child() # <expr_list>
+                is_key = not self._is_record_constructor()
+                if is_key:
+                    self._write_bracket_sp()
+                self._format_child(hints=hints) # <expr_list>
+                if is_key:
+                    self._write_bracket_sp()
             else:
                 self._write_sp()
             self._format_child(hints=Hint.NO_LB_BEFORE) # ']
@@ -1233,11 +1539,26 @@ class ExprFormatter(SpaceSeparatedFormatter):
             self._format_child() # <func_body>
 
         elif ct2 == '(':
-            # initializers such as table(...)
-            self._format_child() # 'table' etc
+            # initializers such as table(...), and function calls. Calls
+            # that may expand indent first, so their measurement starts at
+            # the line's actual column.
+            if self._is_expandable_call():
+                self._write_indent()
+
+            # Must call these before we consume:
+            if self._is_long_constructor():
+                hints = Hint.ONE_PER_LINE | Hint.TRAILING_COMMA
+            elif self._is_long_call():
+                hints = Hint.ONE_PER_LINE # The ')' follows the last argument
+            else:
+                hints = Hint.NONE
+
+            self._format_child() # 'table', <expr>, etc
             self._format_child(hints=Hint.NO_LB_BEFORE) # '('
             if self._get_child_name() == 'expr_list':
-                self._format_child()
+                if hints != Hint.NONE:
+                    self._write_nl()
+                self._format_child(hints=hints) # <expr_list>
             self._format_child(hints=Hint.NO_LB_BEFORE) # ')'
             if self._get_child_name() == 'attr_list':
                 self._write_sp()
@@ -1253,12 +1574,16 @@ class ExprFormatter(SpaceSeparatedFormatter):
             # We do this so we can line-break complex boolean expressions so
             # that each toplevel one ends on a new line, starting with the
             # boolean operand. OutputStream's handling of the GOOD_AFTER_LB
-            # hint implements this.
+            # hint implements this. When the operators should trail instead,
+            # the GOOD_BEFORE_LB hint breaks the line after them.
             hints = None
 
             if self._is_expr_chain_of(ExprFormatter._is_binary_boolean):
                 # Okay! It's AND/ORs all the way up to something not an expr.
-                hints = Hint.GOOD_AFTER_LB
+                if self.ostream.options.operator_position == OperatorPosition.TRAILING:
+                    hints = Hint.GOOD_BEFORE_LB
+                else:
+                    hints = Hint.GOOD_AFTER_LB
 
             self._format_child() # <expr>
             self._write_sp()
@@ -1266,6 +1591,22 @@ class ExprFormatter(SpaceSeparatedFormatter):
             self._write_sp()
             self._format_child() # <expr>
 
+        elif self._is_ternary():
+            # Conditionals that don't fit the line get each branch on a line of
+            # its own, led by its '?' or ':' and indented one more level. The
+            # branches get that indentation too, so nested conditionals that
+            # don't fit either indent further.
+            expand = self._is_long_ternary() # Must call before we consume
+            self._format_child() # <expr>
+            for _ in range(2):
+                if expand:
+                    self._write_nl()
+                else:
+                    self._write_sp()
+                self._format_child(indent=expand, hints=Hint.NO_LB_AFTER) # '?' / ':'
+                self._write_sp()
+                self._format_child(indent=expand) # <expr>
+
         elif self._is_string_concat():
             # This helps OutputStream nicely align long strings broken into
             # substrings concatenated by "+".
@@ -1280,13 +1621,26 @@ class ExprFormatter(SpaceSeparatedFormatter):
             super().format()
 
 
+class HexFormatter(Formatter):
+    """Hex constants, with their digits' case adjusted as configured."""
+    def format(self):
+        hex_case = self.ostream.options.hex_case
+        data = self.script.get_content(*self.node.script_range())
+
+        if hex_case != HexCase.PRESERVE and data[:2].lower() == b'0x':
+            digits = data[2:].upper() if hex_case == HexCase.UPPER else data[2:].lower()
+            data = b'0x' + digits
+
+        self._write(data)
+
+
 class NlFormatter(Formatter):
     """Newline formatting.
 
     Newlines get eliminated at the beginning or end of a sequence of child nodes
     (because such leading and trailing whitespace looks weird), while repeated
-    newlines in mid-sequence are preserved but reduced to no more than one blank
-    line.
+    newlines in mid-sequence are preserved but reduced to no more than the
+    max_blank_lines formatting option's number of blank lines, at least one.
     """
     def format(self):
         node = self.node
@@ -1294,7 +1648,7 @@ class NlFormatter(Formatter):
         if node.next_cst_sibling and node.next_cst_sibling.is_nl():
             return
 
-        # Write a single newline for any sequence of blank lines in the input,
+        # Write the sequence's blank lines, up to the configured maximum,
         # unless this sequence is at the beginning or end of the sequence.
 
         if not node.next_cst_sibling or node.next_cst_sibling.token() == '}':
@@ -1302,13 +1656,51 @@ class NlFormatter(Formatter):
             return
 
         if node.prev_cst_sibling and node.prev_cst_sibling.is_nl():
-            # It's a NL sequence.
+            # It's a NL sequence. All but its first newline are blank lines.
+            blank_lines = 0
             while node.prev_cst_sibling and node.prev_cst_sibling.is_nl():
                 node = node.prev_cst_sibling
+                blank_lines += 1
 
             if node.prev_cst_sibling and node.prev_cst_sibling.token() != '{':
                 # There's something other than whitspace before this sequence.
-                self._write_nl(force=True)
+                max_blank_lines = max(self.ostream.options.max_blank_lines, 1)
+                self._write_nl(num=min(blank_lines, max_blank_lines), force=True)
+
+
+class AttrListFormatter(Formatter):
+    """Attributes, separated by spaces. With the sort_attributes option, they
+    follow the order in ATTRS_ORDER, unknown ones ahead of &deprecated. Lists
+    with comments or newlines keep their order, since those would move along
+    with the attributes."""
+    ATTRS_ORDER = ['&log', '&optional', '&default', '&default_insert', '&priority',
+                   '&add_func', '&delete_func', '&expire_func', '&create_expire',
+                   '&read_expire', '&write_expire', '&on_change', '&raw_output',
+                   '&error_handler', '&type_column', '&backend', '&broker_store',
+                   '&broker_allow_complex_type', '&is_assigned', '&is_used',
+                   '&ordered', '&deprecated', '&redef']
+
+    def format(self):
+        attrs = self.node.nonerr_children
+
+        if self.ostream.options.sort_attributes and not any(
+                node.is_comment() or node.is_nl()
+                for node, _ in self.node.traverse(include_cst=True)):
+            attrs = sorted(attrs, key=self._rank)
+
+        for idx, attr in enumerate(attrs):
+            if idx == 0:
+                self._format_child(attr, hints=self.hints)
+            else:
+                self._write_sp()
+                self._format_child(attr)
+
+    def _rank(self, attr):
+        name = attr.nonerr_children[0].token() if attr.nonerr_children else None
+        try:
+            return self.ATTRS_ORDER.index(name)
+        except ValueError:
+            return self.ATTRS_ORDER.index('&deprecated') - 0.5
 
 
 class AttrFormatter(Formatter):
@@ -1402,7 +1794,6 @@ Formatter.register('hook', FuncHdrVariantFormatter)
 Formatter.register('event', FuncHdrVariantFormatter)
 
 Formatter.register('capture', SpaceSeparatedFormatter)
-Formatter.register('attr_list', SpaceSeparatedFormatter)
 Formatter.register('interval', SpaceSeparatedFormatter)
 Formatter.register('enum_body_elem', SpaceSeparatedFormatter)
 
@@ -0,0 +1,299 @@
+"""This module provides a language server that formats Zeek scripts."""
+import json
+import os
+import urllib.parse
+import urllib.request
+
+from .error import Error
+from .options import FormatOptions
+from .script import format_edits, format_range
+
+# JSON-RPC error codes, as used by the Language Server Protocol.
+LSP_PARSE_ERROR = -32700
+LSP_INVALID_REQUEST = -32600
+LSP_METHOD_NOT_FOUND = -32601
+LSP_INTERNAL_ERROR = -32603
+LSP_REQUEST_FAILED = -32803
+
+# LSP's TextDocumentSyncKind.Full: clients send the full script on changes.
+LSP_SYNC_FULL = 1
+
+# LSP's MessageType.Error, for messages the server logs to the client.
+LSP_MESSAGE_ERROR = 1
+
+
+class LanguageServer:
+    """A Language Server Protocol server for Zeek script formatting.
+
+    It speaks LSP's base protocol over the given binary streams, usually
+    stdin and stdout, and tracks the scripts the client opens. It supports
+    whole-script formatting via format_edits(), formatting of selections via
+    format_range(), and formatting of the statement just completed via
+    on-type formatting, triggered by "}" and ";". Formatting options come from
+    the configuration file that FormatOptions.discover() finds for a script,
+    unless the server got its own options. Editors' formatting settings,
+    such as their tab size, do not apply: Zeek scripts have their own layout.
+    """
+    def __init__(self, istream, ostream, options=None):
+        self._istream = istream
+        self._ostream = ostream
+        self._options = options
+        self._documents = {} # URI -> the script's current content, as str
+        self._shutdown = False
+        self._exited = False
+
+        self._requests = {
+            'initialize': self._initialize,
+            'shutdown': self._do_shutdown,
+            'textDocument/formatting': self._formatting,
+            'textDocument/rangeFormatting': self._range_formatting,
+            'textDocument/onTypeFormatting': self._on_type_formatting,
+        }
+        self._notifications = {
+            'exit': self._exit,
+            'textDocument/didOpen': self._did_open,
+            'textDocument/didChange': self._did_change,
+            'textDocument/didClose': self._did_close,
+        }
+
+    def run(self):
+        """Serves requests until the client exits or closes the input stream.
+
+        Returns 0 when the client asked the server to shut down first, and 1
+        otherwise, as LSP suggests for the server's exit code.
+        """
+        while not self._exited:
+            try:
+                msg = self._read_message()
+            except ValueError as err:
+                self._send_error(None, LSP_PARSE_ERROR, str(err))
+                continue
+
+            if msg is None:
+                break
+
+            self._dispatch(msg)
+
+        return 0 if self._shutdown else 1
+
+    def _dispatch(self, msg):
+        method, params = msg.get('method'), msg.get('params') or {}
+
+        if method is None:
+            # A response to a request of ours. We send none, so there's
+            # nothing to do.
+            return
+
+        if 'id' not in msg:
+            # Notifications get no response, not even for unknown methods.
+            # Failures can't be reported as errors either, so the server
+            # logs them to the client and keeps serving.
+            handler = self._notifications.get(method)
+            if handler is not None:
+                try:
+                    handler(params)
+                except Exception as err: # pylint: disable=broad-except
+                    self._log_error('failed to process "{}": {}'.format(
+                        method, repr(err)))
+            return
+
+        if self._shutdown:
+            self._send_error(msg['id'], LSP_INVALID_REQUEST,
+                             'server is shutting down, "{}" not possible'.format(method))
+            return
+
+        handler = self._requests.get(method)
+        if handler is None:
+            self._send_error(msg['id'], LSP_METHOD_NOT_FOUND,
+                             'unsupported method "{}"'.format(method))
+            return
+
+        try:
+            result = handler(params)
+        except Error as err:
+            self._send_error(msg['id'], LSP_REQUEST_FAILED, str(err))
+        except Exception as err: # pylint: disable=broad-except
+            self._send_error(msg['id'], LSP_INTERNAL_ERROR,
+                             'internal error: {}'.format(err))
+        else:
+            self._send({'jsonrpc': '2.0', 'id': msg['id'], 'result': result})
+
+    def _read_message(self):
+        # Returns the next message, or None at the end of the input. Raises
+        # ValueError for messages that aren't valid JSON-RPC.
+        length = None
+
+        while True:
+            line = self._istream.readline()
+            if not line:
+                return None
+            line = line.strip()
+            if not line:
+                if length is not None:
+                    break
+                continue # Tolerate stray newlines between messages.
+            name, _, value = line.partition(b':')
+            if name.strip().lower() == b'content-length':
+                length = int(value)
+
+        try:
+            msg = json.loads(self._istream.read(length).decode('UTF-8'))
+        except UnicodeDecodeError as err:
+            raise ValueError('invalid message: {}'.format(err)) from err
+
+        if not isinstance(msg, dict):
+            raise ValueError('invalid message: not a JSON object')
+
+        return msg
+
+    def _send(self, msg):
+        body = json.dumps(msg).encode('UTF-8')
+        self._ostream.write('Content-Length: {}\r\n\r\n'.format(len(body)).encode('ascii'))
+        self._ostream.write(body)
+        self._ostream.flush()
+
+    def _send_error(self, msg_id, code, message):
+        self._send({'jsonrpc': '2.0', 'id': msg_id,
+                    'error': {'code': code, 'message': message}})
+
+    def _log_error(self, message):
+        self._send({'jsonrpc': '2.0', 'method': 'window/logMessage',
+                    'params': {'type': LSP_MESSAGE_ERROR, 'message': message}})
+
+    def _initialize(self, _params):
+        from . import __version__ # pylint: disable=import-outside-toplevel
+        return {
+            'capabilities': {
+                'textDocumentSync': LSP_SYNC_FULL,
+                'documentFormattingProvider': True,
+                'documentRangeFormattingProvider': True,
+                'documentOnTypeFormattingProvider': {
+                    'firstTriggerCharacter': '}',
+                    'moreTriggerCharacter': [';'],
+                },
+            },
+            'serverInfo': {'name': 'zeekscript', 'version': __version__},
+        }
+
+    def _do_shutdown(self, _params):
+        self._shutdown = True
+
+    def _exit(self, _params):
+        self._exited = True
+
+    def _did_open(self, params):
+        doc = params['textDocument']
+        self._documents[doc['uri']] = doc['text']
+
+    def _did_change(self, params):
+        # With full synchronization, the last change has the whole script.
+        changes = params['contentChanges']
+        if changes:
+            self._documents[params['textDocument']['uri']] = changes[-1]['text']
+
+    def _did_close(self, params):
+        self._documents.pop(params['textDocument']['uri'], None)
+
+    def _formatting(self, params):
+        uri = params['textDocument']['uri']
+        source = self._document(uri).encode('UTF-8')
+        return [_text_edit(edit.start.line, edit.start.character,
+                           edit.end.line, edit.end.character, edit.new_text)
+                for edit in format_edits(source, self._options_for(uri))]
+
+    def _range_formatting(self, params):
+        uri = params['textDocument']['uri']
+        source = self._document(uri).encode('UTF-8')
+        start = _offset(source, params['range']['start'])
+        end = _offset(source, params['range']['end'])
+        return _range_edits(source, format_range(source, start, end, self._options_for(uri)))
+
+    def _on_type_formatting(self, params):
+        uri = params['textDocument']['uri']
+        source = self._document(uri).encode('UTF-8')
+        end = _offset(source, params['position'])
+
+        # Select the typed character, which ends the statement to format.
+        start = max(end - len(params['ch'].encode('UTF-8')), 0)
+
+        try:
+            edit = format_range(source, start, end, self._options_for(uri))
+        except Error:
+            # Scripts are often incomplete while typing. That's no failure,
+            # there's just nothing to format yet.
+            return []
+
+        return _range_edits(source, edit)
+
+    def _document(self, uri):
+        try:
+            return self._documents[uri]
+        except KeyError:
+            raise Error('unknown document "{}"'.format(uri)) from None
+
+    def _options_for(self, uri):
+        if self._options is not None:
+            return self._options
+
+        parts = urllib.parse.urlparse(uri)
+        if parts.scheme == 'file':
+            path = urllib.request.url2pathname(parts.path)
+        else:
+            # As for stdin, resolve settings for the current directory.
+            path = os.path.join(os.getcwd(), 'stdin.zeek')
+
+        return FormatOptions.discover(path)
+
+
+def _offset(source, position):
+    """Returns the byte offset of an LSP position in the given script.
+
+    LSP counts characters in UTF-16 code units. Positions past the end of a
+    line resolve to its end, and those past the last line to the script's end.
+    """
+    lines = source.splitlines(keepends=True)
+
+    if position['line'] >= len(lines):
+        return len(source)
+
+    start = sum(len(line) for line in lines[:position['line']])
+    line = lines[position['line']].rstrip(b'\r\n').decode('UTF-8')
+    units = line.encode('UTF-16-LE')[:2 * position['character']]
+    # A position may split a surrogate pair; such a half doesn't count.
+    prefix = units.decode('UTF-16-LE', errors='ignore')
+
+    return start + len(prefix.encode('UTF-8'))
+
+
+def _position(source, offset):
+    """Returns the LSP line and character of a byte offset in the script."""
+    line, start = 0, 0
+
+    for text in source.splitlines(keepends=True):
+        if offset < start + len(text) or not text.endswith((b'\n', b'\r')):
+            break
+        line += 1
+        start += len(text)
+
+    prefix = source[start:offset].decode('UTF-8', errors='replace')
+    return line, len(prefix.encode('UTF-16-LE')) // 2
+
+
+def _range_edits(source, edit):
+    """Returns the LSP text edits for a RangeEdit, as a list."""
+    if edit.new_text == source[edit.actual_start:edit.actual_end]:
+        return []
+
+    return [_text_edit(*_position(source, edit.actual_start),
+                       *_position(source, edit.actual_end),
+                       edit.new_text.decode('UTF-8', errors='replace'))]
+
+
+def _text_edit(start_line, start_char, end_line, end_char, new_text):
+    return {
+        'range': {
+            'start': {'line': start_line, 'character': start_char},
+            'end': {'line': end_line, 'character': end_char},
+        },
+        'newText': new_text,
+    }
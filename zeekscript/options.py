@@ -0,0 +1,627 @@
+"""This module provides the settings that control script formatting."""
+import copy
+import enum
+import json
+import os
+import re
+
+try:
+    import tomllib
+except ImportError: # Python < 3.11
+    import tomli as tomllib
+
+from .error import ConfigError
+
+# The names of configuration files providing formatting options, in order of
+# precedence when a directory has several. See find_config_file() for details.
+CONFIG_FILES = ('.zeek-format.toml', 'zeekscript.toml')
+
+# The name of EditorConfig files, see FormatOptions.from_editorconfig().
+EDITORCONFIG_FILE = '.editorconfig'
+
+class LineEnding(enum.Enum):
+    """Line endings for formatted scripts, as used by FormatOptions.line_ending."""
+    AUTO = enum.auto() # Whichever ending dominates in the input
+    LF = enum.auto() # "\n"
+    CRLF = enum.auto() # "\r\n"
+    NATIVE = enum.auto() # The platform's line ending, os.linesep
+
+    def resolve(self, source):
+        """Returns the line ending to use, as bytes, for the given input.
+
+        For AUTO, this counts the input's line endings and picks the more
+        frequent of "\n" and "\r\n", falling back to the native one when the
+        input has no line endings or as many of both.
+        """
+        if self == LineEnding.LF:
+            return b'\n'
+        if self == LineEnding.CRLF:
+            return b'\r\n'
+
+        native = os.linesep.encode('UTF-8')
+
+        if self == LineEnding.NATIVE:
+            return native
+
+        crlf = source.count(b'\r\n')
+        lf = source.count(b'\n') - crlf
+
+        if crlf > lf:
+            return b'\r\n'
+        if lf > crlf:
+            return b'\n'
+        return native
+
+
+class SwitchCaseIndent(enum.Enum):
+    """Placement of case labels, as used by FormatOptions.switch_case_indent."""
+    INDENTED = enum.auto() # One level deeper than the switch keyword
+    SAME_AS_SWITCH = enum.auto() # At the level of the switch keyword
+
+
+class BraceStyle(enum.Enum):
+    """Placement of {}-block braces, as used by FormatOptions.brace_style."""
+    WHITESMITHS = enum.auto() # Braces on own lines, indented like the block
+    ALLMAN = enum.auto() # Braces on own lines, at the level of the statement
+    ATTACH = enum.auto() # Opening brace ends the statement's first line
+
+
+class OperatorPosition(enum.Enum):
+    """Where && and || go when long conditions wrap, as used by
+    FormatOptions.operator_position."""
+    LEADING = enum.auto() # At the start of the continuation line
+    TRAILING = enum.auto() # At the end of the line before the break
+
+
+class HexCase(enum.Enum):
+    """Letter case of hex constants' digits, as used by FormatOptions.hex_case."""
+    PRESERVE = enum.auto() # As in the input
+    UPPER = enum.auto() # 0x0A
+    LOWER = enum.auto() # 0x0a
+
+
+# How from_toml() refers to the enum-valued options in error messages.
+_ENUM_DESCRIPTIONS = {
+    LineEnding: 'line ending',
+    SwitchCaseIndent: 'switch case indentation',
+    BraceStyle: 'brace style',
+    OperatorPosition: 'operator position',
+    HexCase: 'hex case',
+}
+
+# The smallest values from_toml() accepts for integer options, with how its
+# error messages describe them.
+_MIN_VALUES = {
+    'max_line_len': (1, 'a positive'),
+    'tab_size': (1, 'a positive'),
+    'max_blank_lines': (0, 'a non-negative'),
+}
+
+
+class FormatOptions:
+    """A set of formatting options.
+
+    Instances start out with defaults that yield zeek-format's standard
+    layout. To adjust individual settings, pass them as keyword arguments to
+    the constructor, for example FormatOptions(enable_linebreaks=False), or
+    set the attributes directly. Unknown option names raise TypeError, so typos
+    don't silently go unnoticed.
+    """
+    def __init__(self, **kwargs):
+        # Whether we'll consider linebreaks at all. When False, long lines
+        # never wrap.
+        self.enable_linebreaks = True
+
+        # The column at which we consider wrapping lines.
+        self.max_line_len = 80
+
+        # How many visible characters we chalk up for a tab when computing
+        # line lengths.
+        self.tab_size = 8
+
+        # Whether to format scripts that have parsing problems. When False,
+        # Script.format() raises zeekscript.ParserError for such scripts
+        # instead of doing its best to format them anyway.
+        self.tolerate_parsing_errors = True
+
+        # Whether to leave scripts with parsing problems untouched. When True
+        # and parsing errors are tolerated, formatting such a script yields
+        # its original content, byte for byte, instead of a best effort. This
+        # suits "format on save" in editors, where scripts are often mid-edit.
+        self.passthrough_on_error = False
+
+        # Whether to keep anonymous functions with a single-statement body on
+        # one line, as in function(i: count): string { return fmt("%d", i); },
+        # when they fit. By default such bodies always expand to a block.
+        self.collapse_short_functions = False
+
+        # Whether to put the elements of set(), vector(), and table()
+        # constructors that don't fit on a line on lines of their own, each
+        # with a trailing comma, so adding an element changes a single line:
+        #
+        #     global ports = set(
+        #         22/tcp,
+        #         80/tcp,
+        #     );
+        #
+        # By default such constructors wrap like other long lines.
+        self.expand_long_constructors = False
+
+        # Whether to put the parameters of function, event, and hook
+        # signatures that don't fit on a line on lines of their own. The
+        # closing parenthesis and any return type follow the last parameter:
+        #
+        #     function handle(
+        #         c: connection,
+        #         payload: string): bool
+        #
+        # By default such signatures wrap like other long lines.
+        self.expand_long_signatures = False
+
+        # Whether to put the arguments of function calls that don't fit on a
+        # line on lines of their own, one level deeper than the line the call
+        # starts on, instead of continuing them where the line wraps. The
+        # closing parenthesis follows the last argument:
+        #
+        #     Log::write(
+        #         Conn::LOG,
+        #         rec);
+        #
+        # Nested calls that don't fit either go one level deeper still. By
+        # default such calls wrap like other long lines.
+        self.expand_long_calls = False
+
+        # Whether to keep [...] constructors, such as record constructors,
+        # on a single line when they fit within max_line_len by themselves.
+        # Long lines then wrap before or after such constructors, not inside
+        # them, including when they're nested in other constructors.
+        self.keep_short_records = False
+
+        # Whether to pad the insides of index brackets with a space, as in
+        # "t[ 1 ]", "v[ 1 : 3 ]", and table key literals like "[ 1, 2 ] = x".
+        # This doesn't affect record constructors or the brackets of types.
+        # By default the brackets hug their contents, as in the base scripts.
+        self.bracket_spacing = False
+
+        # Whether to align the "=" of consecutive entries in multi-line table
+        # initializers, by padding the keys. Record fields keep their "$a=1":
+        #
+        #     [1]   = "one",
+        #     [100] = "hundred",
+        self.align_table_values = False
+
+        # Whether to align the ##< comments trailing consecutive lines, such
+        # as the fields of a record or the values of an enum, to the column
+        # just past the longest of these lines:
+        #
+        #     a: count;       ##< A field
+        #     longer: string; ##< Another field
+        #
+        # Blank lines and changes in indentation start a new group. Lines
+        # reaching max_line_len keep a single space and don't affect the
+        # column. By default each comment follows its line after one space.
+        self.align_field_comments = False
+
+        # The number of consecutive blank lines to keep, at most. Longer runs
+        # of blank lines get reduced to this many. Blank lines the author
+        # put in never vanish entirely, so values below 1 act like 1.
+        self.max_blank_lines = 1
+
+        # Where the braces of function, event, and hook bodies and of
+        # statement blocks go. The default, BraceStyle.WHITESMITHS, is Zeek's
+        # traditional layout, with the braces indented like the block's
+        # statements. BraceStyle.ALLMAN puts them on their own lines at the
+        # level of the surrounding code, and BraceStyle.ATTACH puts the opening
+        # brace at the end of the preceding line, as in "if ( x ) {".
+        self.brace_style = BraceStyle.WHITESMITHS
+
+        # Where the && and || operators go when a long chain of them, such
+        # as an if-statement's condition, wraps at them. By default,
+        # OperatorPosition.LEADING, they start the continuation lines.
+        # OperatorPosition.TRAILING puts them at the end of the lines before.
+        self.operator_position = OperatorPosition.LEADING
+
+        # Where case and default labels go in switch statements. By default,
+        # SwitchCaseIndent.INDENTED, they go one level deeper than the switch
+        # keyword. SwitchCaseIndent.SAME_AS_SWITCH puts them at the level of
+        # the switch keyword instead. Either way, case bodies go one level deeper than
+        # their label.
+        self.switch_case_indent = SwitchCaseIndent.INDENTED
+
+        # Whether to indent preprocessor directives such as @if and @endif
+        # like the declarations or statements around them. The code they
+        # guard stays indented as it would be without them, so nested
+        # conditionals don't add levels. By default the directives go to the
+        # beginning of the line.
+        self.indent_preproc_directives = False
+
+        # The letter case of the digits in hex constants such as 0x0a. With
+        # HexCase.UPPER or HexCase.LOWER, all digits get that case and the
+        # prefix becomes "0x". The default, HexCase.PRESERVE, leaves hex
+        # constants as they are.
+        self.hex_case = HexCase.PRESERVE
+
+        # Whether to sort runs of consecutive @load directives by the loaded
+        # path. The same goes for @load-plugin and @load-sigs, each in runs of
+        # their own. Blank lines and comments end a run, so intentional
+        # grouping survives. Identical lines within a run collapse into one.
+        self.sort_loads = False
+
+        # Whether to put the attributes of declarations, record fields, and
+        # the like into a canonical order: &log, &optional, and &default
+        # first, &deprecated and &redef last, and others in between, as in
+        # "&log &optional &default=0 &redef". Each attribute keeps its value.
+        # Attribute lists with comments stay as they are. By default the
+        # attributes keep the order their author gave them.
+        self.sort_attributes = False
+
+        # Whether to rewrap the text of consecutive ## and ##! documentation
+        # comments to fit max_line_len. Blank comment lines separate
+        # paragraphs. Lines that look like markup, such as ".." directives,
+        # "-" or "*" list items, ":" fields, or indented blocks, stay as they
+        # are. Lines starting with a parameter name, such as "c: The
+        # connection.", start a new paragraph. ##< comments never change.
+        self.reflow_doc_comments = False
+
+        # The line ending to use in the formatted script. With the default,
+        # LineEnding.AUTO, scripts keep their dominant line ending, and mixed
+        # line endings get normalized to it.
+        self.line_ending = LineEnding.AUTO
+
+        # Whether the formatted script ends in exactly one newline. When
+        # False, it ends without one, which helps when embedding formatted
+        # snippets in other content. Either way, trailing blank lines go away.
+        self.final_newline = True
+
+        # Whether a UTF-8 byte order mark at the beginning of a script stays
+        # in place. When False, the formatted script starts without one.
+        # Formatting never adds a mark to scripts that lack one.
+        self.keep_byte_order_mark = True
+
+        # Whether to verify that the formatting is stable, by formatting the
+        # result a second time. When that changes anything, Script.format()
+        # raises zeekscript.IdempotencyError. This doubles the formatting
+        # effort, so it's off by default.
+        self.check_idempotency = False
+
+        # The string making up one level of indentation: a tab by default, but
+        # any number of spaces works as well, such as "    ". An integer is
+        # shorthand for that many spaces, so 4 is the same as "    ".
+        self.indent = '\t'
+
+        for key, val in kwargs.items():
+            if not hasattr(self, key):
+                raise TypeError('unknown formatting option "{}"'.format(key))
+            setattr(self, key, val)
+
+    def get_indent(self):
+        """Returns one level of indentation as bytes, as per the indent
+        option. This doesn't validate the result."""
+        indent = self.indent
+        if isinstance(indent, int):
+            indent = ' ' * indent
+        if isinstance(indent, str):
+            indent = indent.encode('UTF-8')
+        return indent
+
+    def to_dict(self):
+        """Returns the options as a dict, as from_toml() reads them.
+
+        The keys are the option names, in the order of their declaration. The
+        values are booleans, integers, and strings, with the enum-valued
+        options as the lowercased names of their members, as in "crlf". This
+        suits serialization to JSON or TOML, and from_toml() turns the result's
+        TOML rendering back into equal options.
+        """
+        return {key: val.name.lower() if isinstance(val, enum.Enum) else val
+                for key, val in vars(self).items()}
+
+    def to_toml(self):
+        """Returns the options as TOML content, as a string.
+
+        This renders to_dict() one option per line, so from_toml() and
+        configuration files read it back into the same options.
+        """
+        lines = []
+
+        for key, val in self.to_dict().items():
+            if isinstance(val, bool):
+                val = 'true' if val else 'false'
+            elif isinstance(val, str):
+                # JSON's string escapes are valid in TOML's basic strings.
+                val = json.dumps(val, ensure_ascii=False)
+            lines.append('{} = {}\n'.format(key, val))
+
+        return ''.join(lines)
+
+    @classmethod
+    def from_toml(cls, content, base=None):
+        """Returns a FormatOptions instance configured via TOML content.
+
+        content is a string or bytes with top-level keys named like the
+        options, for example:
+
+            max_line_len = 100
+            indent = 4
+            line_ending = "lf"
+
+        The enum-valued options, such as line_ending and brace_style, take
+        the member names of their enums, in any case. Options
+        not mentioned keep their values from base, a FormatOptions instance,
+        or their defaults without one. Raises zeekscript.ConfigError for
+        invalid TOML, unknown option names, values of the wrong type, and
+        out-of-range numbers, such as a max_line_len of zero.
+        """
+        if isinstance(content, bytes):
+            content = content.decode('UTF-8', errors='replace')
+
+        try:
+            config = tomllib.loads(content)
+        except tomllib.TOMLDecodeError as err:
+            raise ConfigError('invalid TOML: {}'.format(err)) from err
+
+        options = copy.copy(base) if base is not None else cls()
+
+        for key, val in config.items():
+            if not hasattr(options, key):
+                raise ConfigError('unknown formatting option "{}"'.format(key))
+
+            default = getattr(options, key)
+
+            if isinstance(default, enum.Enum):
+                etype = type(default)
+                try:
+                    val = etype[str(val).upper()]
+                except KeyError as err:
+                    raise ConfigError('invalid {} "{}", expected one of {}'.format(
+                        _ENUM_DESCRIPTIONS[etype], val,
+                        ', '.join('"{}"'.format(e.name.lower()) for e in etype))) from err
+            elif key == 'indent':
+                # A string, or an integer number of spaces.
+                if isinstance(val, bool) or not isinstance(val, (int, str)):
+                    raise ConfigError('option "indent" requires a string or an integer')
+            elif isinstance(default, bool):
+                if not isinstance(val, bool):
+                    raise ConfigError('option "{}" requires a boolean value'.format(key))
+            elif isinstance(val, bool) or not isinstance(val, int): # bools are ints, too
+                raise ConfigError('option "{}" requires an integer value'.format(key))
+            elif key in _MIN_VALUES and val < _MIN_VALUES[key][0]:
+                raise ConfigError('option "{}" requires {} integer'.format(
+                    key, _MIN_VALUES[key][1]))
+
+            setattr(options, key, val)
+
+        return options
+
+    @classmethod
+    def from_file(cls, path, base=None):
+        """Returns a FormatOptions instance configured via the given TOML file.
+
+        path is a filename string or pathlib.Path, and base works as for
+        from_toml(). Raises zeekscript.ConfigError when the file cannot be
+        read or contains problems, as described in from_toml().
+        """
+        try:
+            with open(path, 'rb') as hdl:
+                content = hdl.read()
+        except OSError as err:
+            raise ConfigError('cannot read {}: {}'.format(path, err)) from err
+
+        try:
+            return cls.from_toml(content, base)
+        except ConfigError as err:
+            raise ConfigError('{}: {}'.format(path, err)) from err
+
+    @classmethod
+    def discover(cls, start):
+        """Returns a FormatOptions instance configured for the given location.
+
+        This uses the configuration file that find_config_file() locates for
+        start, and the defaults when there is none. Raises
+        zeekscript.ConfigError like from_file().
+        """
+        path = find_config_file(start)
+        return cls() if path is None else cls.from_file(path)
+
+    @classmethod
+    def from_editorconfig(cls, path):
+        """Returns a FormatOptions instance configured via .editorconfig files.
+
+        path is the name of the script to format, which need not exist. This
+        resolves the .editorconfig settings applying to it, as described at
+        https://editorconfig.org, and maps them onto formatting options:
+
+        - indent_style and indent_size determine the indentation: a tab, or
+          indent_size spaces, 4 when unspecified.
+        - tab_width, or indent_size for tab indentation, sets tab_size.
+        - end_of_line sets line_ending, for "lf" and "crlf".
+        - insert_final_newline sets final_newline.
+
+        Options that the settings don't cover keep their defaults.
+        """
+        settings = _editorconfig_settings(path)
+        options = cls()
+
+        tab_width = settings.get('tab_width', settings.get('indent_size'))
+        if tab_width is not None and tab_width.isdigit() and int(tab_width) > 0:
+            options.tab_size = int(tab_width)
+
+        if settings.get('indent_style') == 'tab':
+            options.indent = '\t'
+        elif settings.get('indent_style') == 'space':
+            size = settings.get('indent_size', '4')
+            options.indent = int(size) if size.isdigit() and int(size) > 0 else 4
+
+        if settings.get('end_of_line') in ('lf', 'crlf'):
+            options.line_ending = LineEnding[settings['end_of_line'].upper()]
+
+        if settings.get('insert_final_newline') in ('true', 'false'):
+            options.final_newline = settings['insert_final_newline'] == 'true'
+
+        return options
+
+
+def find_config_file(start):
+    """Returns the path of the configuration file that applies to a location.
+
+    start is a file or directory path. This searches start's directory (start
+    itself, for directories) and its parents for a .zeek-format.toml or
+    zeekscript.toml file and returns the first one it finds, so the closest
+    file wins. Within a directory, .zeek-format.toml takes precedence. Returns
+    None when there is none.
+    """
+    path = os.path.abspath(start)
+    if not os.path.isdir(path):
+        path = os.path.dirname(path)
+
+    while True:
+        for name in CONFIG_FILES:
+            candidate = os.path.join(path, name)
+            if os.path.isfile(candidate):
+                return candidate
+
+        parent = os.path.dirname(path)
+        if parent == path:
+            return None
+        path = parent
+
+
+def _editorconfig_settings(path):
+    """Returns the .editorconfig settings applying to the given file.
+
+    This reads the .editorconfig files in the file's directory and its
+    parents, up to one declaring root = true, and returns a dict of the
+    lowercased settings from all sections whose glob matches the file. Closer
+    files take precedence over those further up, and within a file later
+    sections over earlier ones. Settings with value "unset" are absent.
+    """
+    path = os.path.abspath(path)
+    dirname = os.path.dirname(path)
+    configs = [] # (directory, sections) tuples, closest first
+
+    while True:
+        fname = os.path.join(dirname, EDITORCONFIG_FILE)
+        if os.path.isfile(fname):
+            is_root, sections = _read_editorconfig(fname)
+            configs.append((dirname, sections))
+            if is_root:
+                break
+
+        parent = os.path.dirname(dirname)
+        if parent == dirname:
+            break
+        dirname = parent
+
+    settings = {}
+
+    for dirname, sections in reversed(configs):
+        relpath = os.path.relpath(path, dirname).replace(os.sep, '/')
+        for (regex, ranges), section in sections:
+            match = regex.fullmatch(relpath)
+            # Numeric ranges only match numbers, so check their bounds too.
+            if match and all(num is None or low <= int(num) <= high
+                             for num, (low, high) in zip(match.groups(), ranges)):
+                settings.update(section)
+
+    return {key: val for key, val in settings.items() if val != 'unset'}
+
+
+def _read_editorconfig(fname):
+    """Parses the given .editorconfig file.
+
+    Returns a tuple of whether the file declares root = true, and a list of
+    (translated glob, settings dict) tuples for its sections, in order.
+    Unreadable files and malformed lines get skipped.
+    """
+    is_root = False
+    sections = []
+    section = None
+
+    try:
+        with open(fname, 'rb') as hdl:
+            lines = hdl.read().decode('UTF-8', errors='replace').splitlines()
+    except OSError:
+        return is_root, sections
+
+    for line in lines:
+        line = line.strip()
+        if not line or line[0] in '#;':
+            continue
+
+        if line.startswith('[') and line.endswith(']'):
+            section = {}
+            try:
+                sections.append((_editorconfig_regex(line[1:-1]), section))
+            except re.error:
+                pass # A glob we cannot handle, so its settings go unused.
+            continue
+
+        key, sep, val = line.partition('=')
+        if not sep:
+            continue
+
+        key, val = key.strip().lower(), val.strip().lower()
+
+        if section is not None:
+            section[key] = val
+        elif key == 'root':
+            is_root = val == 'true'
+
+    return is_root, sections
+
+
+def _editorconfig_regex(pattern):
+    """Translates an EditorConfig section glob into a regex and number ranges.
+
+    The regex matches paths relative to the .editorconfig file's directory,
+    "/"-separated. Globs without a slash match file names at any depth.
+    Returns a tuple of the regex and a list of the glob's {low..high} number
+    ranges as (low, high) tuples. The regex captures the number for each
+    range, in order, for comparison against its bounds.
+    """
+    res = '' if '/' in pattern else '(?:.*/)?'
+    pattern = pattern[1:] if pattern.startswith('/') else pattern
+    ranges = []
+    idx = 0
+    braces = 0
+
+    while idx < len(pattern):
+        char = pattern[idx]
+
+        if pattern.startswith('**', idx):
+            res += '.*'
+            idx += 1
+        elif char == '*':
+            res += '[^/]*'
+        elif char == '?':
+            res += '[^/]'
+        elif char == '[' and ']' in pattern[idx+1:]:
+            end = pattern.index(']', idx + 1)
+            chars = pattern[idx+1:end].replace('\\', '\\\\')
+            if chars.startswith('!'):
+                chars = '^' + chars[1:]
+            res += '[' + chars + ']'
+            idx = end
+        elif char == '{' and '}' in pattern[idx+1:]:
+            num_range = re.match(r'\{(-?\d+)\.\.(-?\d+)\}', pattern[idx:])
+            if num_range:
+                ranges.append(tuple(sorted(int(num) for num in num_range.groups())))
+                res += r'(-?\d+)'
+                idx += num_range.end() - 1
+            else:
+                res += '(?:'
+                braces += 1
+        elif char == ',' and braces:
+            res += '|'
+        elif char == '}' and braces:
+            res += ')'
+            braces -= 1
+        elif char == '\\' and idx + 1 < len(pattern):
+            idx += 1
+            res += re.escape(pattern[idx])
+        else:
+            res += re.escape(char)
+
+        idx += 1
+
+    return re.compile(res), ranges
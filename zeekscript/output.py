@@ -1,7 +1,9 @@
+import io
 import os
 import sys
 
 from .formatter import Formatter, Hint
+from .options import FormatOptions
 
 class Output:
     """A chunk of data to write out.
@@ -20,17 +22,31 @@ class OutputStream:
     manager, it also ensures that exiting context always finishes output with a
     newline.
     """
-    MAX_LINE_LEN = 80 # Column at which we consider wrapping.
     MIN_LINE_ITEMS = 5 # Required items on a line to consider wrapping.
     MIN_LINE_EXCESS = 5 # Minimum characters that a line needs to be too long.
-    TAB_SIZE = 8 # How many visible characters we chalk up for a tab.
-    SPACE_INDENT = 4 # When wrapping, add this many spaces onto tab-indentation.
+    SPACE_INDENT = 4 # When wrapping, add this many spaces onto indentation.
 
-    def __init__(self, ostream, enable_linebreaks=True):
-        """OutputStream constructor. The ostream argument is a file-like object."""
+    def __init__(self, ostream, enable_linebreaks=True, options=None):
+        """OutputStream constructor.
+
+        The ostream argument is a file-like object. options, if provided, is a
+        zeekscript.FormatOptions instance that takes precedence over the
+        enable_linebreaks flag.
+        """
         self._ostream = ostream
+        self.options = options or FormatOptions(enable_linebreaks=enable_linebreaks)
         self._col = 0 # 0-based column the next character goes into.
-        self._tab_indent = 0 # Number of tabs indented in current line
+        self._tab_indent = 0 # Number of indentation levels in current line
+
+        self._max_line_len = self.options.max_line_len # Column to consider wrapping at
+        self._tab_size = self.options.tab_size # Visible width of a tab
+
+        # The byte sequence making up a single indentation level. An integer
+        # means that many spaces.
+        self._indent = self.options.get_indent()
+        if not self._indent or self._indent.strip(b' \t'):
+            raise ValueError('indentation must consist of tabs or spaces, not {!r}'
+                             .format(self.options.indent))
 
         # Series of Output objects that makes up a formatted but un-wrapped line.
         self._linebuffer = []
@@ -44,10 +60,10 @@ class OutputStream:
         # never wrap. When True, linebreaks will generally happen, but
         # formatters may pause them temporarily via the _use_linebreaks flag
         # below.
-        self._enable_linebreaks = enable_linebreaks
+        self._enable_linebreaks = self.options.enable_linebreaks
 
         self._use_linebreaks = True # Whether line-breaking is in effect.
-        self._use_tab_indent = True # Whether tab-indentation is in effect.
+        self._use_tab_indent = True # Whether indentation is in effect.
 
         # Whether to tuck on space-alignments independently of our own linebreak
         # logic. (Some formatters request this.) These alignments don't
@@ -93,7 +109,7 @@ class OutputStream:
     def write_tab_indent(self, formatter):
         if self._use_tab_indent:
             self._tab_indent = formatter.indent
-            self.write(b'\t' * self._tab_indent, formatter)
+            self.write(self._indent * self._tab_indent, formatter)
 
     def write_space_align(self, formatter):
         if self._use_space_align:
@@ -102,6 +118,22 @@ class OutputStream:
     def get_column(self):
         return self._col
 
+    def fits(self, num):
+        """Predicate, returns True if num more characters fit on the current line."""
+        col = self._indent_width() + max(self._col - len(self._indent) * self._tab_indent, 0)
+        return col + num <= self._max_line_len
+
+    def fits_own_line(self, num):
+        """Predicate, returns True if num characters fit on a line by
+        themselves, at the current line's indentation."""
+        return self._indent_width() + num <= self._max_line_len
+
+    def _indent_width(self):
+        """Returns the visible width of the current line's indentation."""
+        width = sum(self._tab_size if char == ord('\t') else 1
+                    for char in self._indent)
+        return self._tab_indent * width
+
     def _flush_line(self):
         """Flushes out the line buffer, stripping trailing whitespace.
 
@@ -125,6 +157,7 @@ class OutputStream:
         tbd_len = 0 # Length of the to-be-done output (in characters)
         line_items = 0 # Number of items (tokens, not whitespace) on formatted line
         using_break_hints = False # Whether we've used advisory linebreak hints yet
+        break_pending = False # Whether to break before the next item
 
         def flush_tbd():
             nonlocal tbd, tbd_len, col_flushed
@@ -137,9 +170,9 @@ class OutputStream:
         def write_linebreak():
             nonlocal tbd, tbd_len, col_flushed
             self._write(Formatter.NL)
-            self._write(b'\t' * self._tab_indent)
+            self._write(self._indent * self._tab_indent)
             self._write(b' ' * self.SPACE_INDENT)
-            col_flushed = self._tab_indent * self.TAB_SIZE + self.SPACE_INDENT
+            col_flushed = self._indent_width() + self.SPACE_INDENT
 
             # Remove any pure whitespace from the beginning of the
             # continuation of the line we just broke:
@@ -184,22 +217,29 @@ class OutputStream:
             # conditionals. This needs to take precedence over NO_LB_AFTER,
             # see next condition.
             cnd_good_after_lb = (Hint.GOOD_AFTER_LB in out.formatter.hints and
-                                 self._col > self.MAX_LINE_LEN)
+                                 self._col > self._max_line_len)
+
+            # Mirroring the above, if the line is too long and this chunk says
+            # it best precedes a break, then break before the next one.
+            cnd_good_before_lb = (Hint.GOOD_BEFORE_LB in out.formatter.hints and
+                                  self._col > self._max_line_len)
 
-            # If the caller requested no line break, abide.
-            cnd_no_lb_after = Hint.NO_LB_AFTER in out.formatter.hints
+            # If the caller requested no line break, abide. The same goes for
+            # anything inside an item that we keep on one line.
+            cnd_no_lb_after = (Hint.NO_LB_AFTER in out.formatter.hints or
+                               Hint.KEEP_TOGETHER in out.formatter.hints)
 
             # Similarly, if we git GOOD_AFTER_LB earlier, abide.
             cnd_no_break_hints = not using_break_hints
 
-            # We need to exceed the MAX_LINE_LEN limit with what's pending.
-            cnd_line_too_long = col_flushed + tbd_len > self.MAX_LINE_LEN
+            # We need to exceed the line length limit with what's pending.
+            cnd_line_too_long = col_flushed + tbd_len > self._max_line_len
 
             # The pending length must be "worth it". That is, don't break if the
             # TBD len is just a little bit over. But do so if we're just too
             # long overall now.
             cnd_enough_excess = (tbd_len >= self.MIN_LINE_EXCESS or
-                                 col_flushed > self.MAX_LINE_LEN + self.MIN_LINE_EXCESS)
+                                 col_flushed > self._max_line_len + self.MIN_LINE_EXCESS)
 
             # If there are only very few items on the line to begin with, don't
             # bother: breaking these also looks messy. This often covers the
@@ -211,14 +251,24 @@ class OutputStream:
             # long strings looking silly when alone on a new line. (This doesn't
             # interfere with bit-by-bit repeated linebreaks of a very long line
             # -- that still happens when we build up the next TBD batch.)
-            cnd_no_addl_wrap =  self._tab_indent * self.TAB_SIZE + tbd_len < self.MAX_LINE_LEN
+            cnd_no_addl_wrap =  self._indent_width() + tbd_len < self._max_line_len
 
             # Helpful for tracing linebreak decision-making:
-            # print_error('XXX gal:%d nla:%d nbh:%d tl:%d ex:%d ei:%d naw:%d | %s %s %s' % (
-            #    cnd_good_after_lb, cnd_no_lb_after, cnd_no_break_hints,
+            # print_error('XXX gal:%d gbl:%d nla:%d nbh:%d tl:%d ex:%d ei:%d naw:%d | %s %s %s' % (
+            #    cnd_good_after_lb, cnd_good_before_lb, cnd_no_lb_after, cnd_no_break_hints,
             #    cnd_line_too_long, cnd_enough_excess, cnd_enough_line_items,
             #    cnd_no_addl_wrap, out.data, col_flushed, tbd_len))
 
+            # Break as requested by the previous chunk. This doesn't preclude
+            # the decisions below.
+            if break_pending:
+                write_linebreak()
+                break_pending = False
+
+            if cnd_good_before_lb:
+                break_pending = True
+                using_break_hints = True
+
             # If the line is too long and this chunk says it best follows a
             # break, then break now. This helps align e.g. multi-part boolean
             # conditionals. This needs to take precedence over NO_LB_AFTER.
@@ -262,12 +312,7 @@ class OutputStream:
         self._writebuffer = []
 
         try:
-            if self._ostream == sys.stdout:
-                # Clunky: must write string here, not bytes. We could
-                # use _ostream.buffer -- not sure how portable that is.
-                self._ostream.write(output.decode('UTF-8'))
-            else:
-                self._ostream.write(output)
+            write_bytes(self._ostream, output)
         except BrokenPipeError:
             #  https://docs.python.org/3/library/signal.html#note-on-sigpipe:
             devnull = os.open(os.devnull, os.O_WRONLY)
@@ -275,6 +320,20 @@ class OutputStream:
             sys.exit(1)
 
 
+def write_bytes(ostream, data):
+    """Writes bytes to the given file-like object.
+
+    Text streams, such as sys.stdout or io.StringIO instances, receive the
+    data decoded from UTF-8. Everything else receives it as-is.
+    """
+    if isinstance(ostream, io.TextIOBase):
+        # Clunky: must write string here, not bytes. We could use the
+        # stream's buffer, but not all text streams have one.
+        ostream.write(data.decode('UTF-8'))
+    else:
+        ostream.write(data)
+
+
 def print_error(*args, **kwargs):
     """A print() wrapper that writes to stderr."""
     print(*args, file=sys.stderr, **kwargs)
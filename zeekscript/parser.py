@@ -1,7 +1,9 @@
 """This module contains parser tooling for the zeekscript package."""
+import hashlib
 import os
 import pathlib
 import sys
+import threading
 
 try:
     # In order to use the tree-sitter parser we need to load the TS language .so
@@ -23,12 +25,26 @@ except ImportError:
     print('This package requires the tree_sitter package.')
     sys.exit(1)
 
+from .error import ParserError
+
 
 class Parser:
-    """tree_sitter.Parser abstraction that takes care of loading the TS Zeek language."""
+    """tree_sitter.Parser abstraction that takes care of loading the TS Zeek language.
+
+    The Zeek language and the tree_sitter.Parser using it get loaded only once,
+    upon first instantiation, and are shared by all subsequent instances. This
+    keeps repeated parsing (say, when formatting many scripts in a loop) cheap.
+    """
     TS_PARSER = None # A tree_sitter.Parser singleton
+    TS_PARSER_LOCK = threading.Lock() # Guards the singleton's creation
 
     def __init__(self):
+        """Parser constructor.
+
+        Raises zeekscript.ParserError when the Zeek language bindings cannot
+        be loaded, so problems with the installation surface here rather than
+        during parsing.
+        """
         Parser.load_parser()
 
     def parse(self, text):
@@ -41,12 +57,62 @@ class Parser:
 
     @classmethod
     def load_parser(cls):
-        if cls.TS_PARSER is None:
+        if cls.TS_PARSER is not None:
+            return
+
+        with cls.TS_PARSER_LOCK:
+            # Another thread may have beaten us to it while we waited.
+            if cls.TS_PARSER is not None:
+                return
+
             # Python voodoo to access the bindings library contained in this
             # package regardless of how we're loading the package. Details:
             # https://importlib-resources.readthedocs.io/en/latest/using.html#file-system-or-zip-file
             source = files(__package__).joinpath('zeek-language.so')
-            with as_file(source) as lib:
-                zeek_lang = tree_sitter.Language(str(lib), 'zeek')
-            cls.TS_PARSER = tree_sitter.Parser()
-            cls.TS_PARSER.set_language(zeek_lang)
+            try:
+                with as_file(source) as lib:
+                    zeek_lang = tree_sitter.Language(str(lib), 'zeek')
+            except (OSError, AttributeError) as err:
+                raise ParserError('cannot load Zeek language bindings: {}'.format(
+                    err)) from err
+            ts_parser = tree_sitter.Parser()
+            ts_parser.set_language(zeek_lang)
+            cls.TS_PARSER = ts_parser
+
+
+class FormatterMetadata:
+    """Version information about the formatter, as returned by metadata().
+
+    formatter_version is the zeekscript package version. grammar_version is
+    the version of the tree-sitter-zeek grammar the package got built with,
+    or None when unknown. grammar_hash is the SHA-256 hex digest of the
+    compiled grammar bindings, or None when they're missing. Tools caching
+    formatting results can use these to notice when formatting may change.
+    """
+    def __init__(self, formatter_version, grammar_version, grammar_hash):
+        self.formatter_version = formatter_version
+        self.grammar_version = grammar_version
+        self.grammar_hash = grammar_hash
+
+    def __repr__(self):
+        return 'FormatterMetadata({!r}, {!r}, {!r})'.format(
+            self.formatter_version, self.grammar_version, self.grammar_hash)
+
+
+def metadata():
+    """Returns a FormatterMetadata instance describing this installation."""
+    from . import __version__ # pylint: disable=import-outside-toplevel
+
+    package = files(__package__)
+
+    try:
+        grammar_version = package.joinpath('zeek-language.version').read_text().strip() or None
+    except OSError:
+        grammar_version = None
+
+    try:
+        grammar_hash = hashlib.sha256(package.joinpath('zeek-language.so').read_bytes()).hexdigest()
+    except OSError:
+        grammar_hash = None
+
+    return FormatterMetadata(__version__, grammar_version, grammar_hash)
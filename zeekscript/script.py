@@ -1,13 +1,88 @@
+import concurrent.futures
+import copy
+import difflib
+import enum
+import hashlib
+import io
 import os
 import pathlib
+import re
 import sys
+import tempfile
+import unicodedata
 
-from .error import FileError, ParserError
+from .error import (Diagnostic, EncodingError, Error, FileError, IdempotencyError,
+                    ParserError)
 from .formatter import Formatter
 from .node import Node
-from .output import OutputStream
+from .options import FormatOptions
+from .output import OutputStream, print_error, write_bytes
 from .parser import Parser
 
+# A line in a multi-line table initializer that assigns to an index: groups
+# are the indentation, the "[...]" key, the whitespace after it, and
+# everything from the "=".
+ALIGNABLE_ASSIGNMENT = re.compile(rb'^([ \t]*)(\[[^=]*?\])([ \t]+)(=(?!=).*)$')
+
+# A line with a trailing ##< comment: groups are the indentation, the code,
+# and the comment.
+TRAILING_DOC_COMMENT = re.compile(rb'^([ \t]*)(.*?\S)[ \t]+(##<.*)$')
+
+# A line continuing the ##< comment of a previous one: groups are the
+# leading whitespace and the comment.
+CONTINUED_DOC_COMMENT = re.compile(rb'^([ \t]*)(##<.*)$')
+
+# A toplevel @load, @load-plugin, or @load-sigs directive, possibly followed
+# by a comment. The group captures the directive.
+LOAD_DIRECTIVE = re.compile(rb'^(@load(?:-plugin|-sigs)?)[ \t]')
+
+# A line consisting of a ## or ##! documentation comment with text, but not
+# ##<. Groups are the indentation, the comment marker, and the text after the
+# space following it.
+DOC_COMMENT = re.compile(rb'^([ \t]*)(##!?)(?![<#!]) (.*)$')
+
+# Documentation text that reflowing leaves alone because it's likely markup:
+# reST directives, list items, field lists, and indented blocks.
+DOC_COMMENT_VERBATIM = re.compile(rb'^(\.\.|[-*:]|[ \t])')
+
+# Documentation text that starts a new paragraph when reflowing, such as
+# "c: The connection." in the description of an event's parameters.
+DOC_COMMENT_PARAM = re.compile(rb'^[\w:]+: ')
+
+# A comment line that disables or re-enables formatting. See
+# Script._apply_directives() for details.
+FORMAT_DIRECTIVE = re.compile(
+    rb'^[ \t]*#[ \t]*(?:zeekfmt|zeek-format):[ \t]*(off|on)[ \t]*\r?$', re.MULTILINE)
+
+# The name of files listing paths to skip when searching for scripts. See
+# collect_zeek_files() for details.
+IGNORE_FILE = '.zeekformatignore'
+
+# The UTF-8 byte order mark that some editors put at the beginning of files.
+UTF8_BOM = b'\xef\xbb\xbf'
+
+class Declaration:
+    """A toplevel declaration in a script, as Script.declarations() reports it.
+
+    kind names the type of declaration: "function", "event", "hook", "global",
+    "const", "option", "redef", "redef_enum", "redef_record", "type", or
+    "module". name is the declared identifier as a string, such as "zeek_init"
+    or "Foo::bar", or None when the declaration doesn't have one. node is the
+    declaration's zeekscript.Node, and start_byte and end_byte delineate it in
+    the script, slice-style.
+    """
+    def __init__(self, kind, name, node):
+        self.kind = kind
+        self.name = name
+        self.node = node
+        self.start_byte = node.start_byte
+        self.end_byte = node.end_byte
+
+    def __repr__(self):
+        return 'Declaration({!r}, {!r}, {}-{})'.format(
+            self.kind, self.name, self.start_byte, self.end_byte)
+
+
 class Script:
     """Representation of a single Zeek script file."""
     def __init__(self, file):
@@ -19,6 +94,7 @@ class Script:
         """
         self.file = file
         self.source = None # The file's full content, once parsed
+        self.bom = b'' # The byte order mark preceding the content, if any
         self.ts_tree = None # The tree-sitter parse tree for the script
         self.root = None # The root node of our cloned (and malleable) tree
 
@@ -34,8 +110,8 @@ class Script:
         try:
             if isinstance(self.file, (str, pathlib.Path)):
                 if str(self.file) == '-':
-                    # tree-sitter expects bytes, not strings, as input.
-                    self.source = sys.stdin.read().encode('UTF-8')
+                    # Read bytes, so stdin gets the same treatment as files.
+                    self.source = sys.stdin.buffer.read()
                 else:
                     with open(self.file, 'rb') as hdl:
                         self.source = hdl.read()
@@ -50,6 +126,12 @@ class Script:
         except OSError as err:
             raise FileError(str(err)) from err
 
+        # A byte order mark isn't part of the script, and confuses the parser.
+        # Set it aside, formatting puts it back in place.
+        if self.source.startswith(UTF8_BOM):
+            self.bom = UTF8_BOM
+            self.source = self.source[len(UTF8_BOM):]
+
         self.ts_tree = Parser().parse(self.source)
 
         if self.ts_tree is None or self.ts_tree.root_node is None:
@@ -97,33 +179,27 @@ class Script:
         """
         assert self.root is not None, 'call Script.parse() before Script.get_error()'
 
-        line, lineno, msg = None, None, None
+        node, msg = self._find_error()
 
-        for node, _ in self.root.traverse():
-            snippet = self.source[node.start_byte:node.end_byte]
-            if len(snippet) > 50:
-                snippet = snippet[:50] + b'[...]'
+        if node is None:
+            return None, None, None
 
-            if node.type == 'ERROR':
-                msg = 'cannot parse line {}, col {}: "{}"'.format(
-                    node.start_point[0], node.start_point[1],
-                    snippet.decode('UTF-8'))
-            elif node.is_missing:
-                msg = 'missing grammar node "{}" on line {}, col {}'.format(
-                    node.type, node.start_point[0], node.start_point[1])
-            elif node.has_error and (not node.children or
-                                     not any((kid.has_error for kid in node.children))):
-                msg = 'grammar node "{}" has error on line {}, col {}'.format(
-                    node.type, node.start_point[0], node.start_point[1])
-            else:
-                continue
+        line = self.source.split(Formatter.NL)[node.start_point[0]]
+        return line.decode('UTF-8', errors='replace'), node.start_point[0], msg
 
-            line = self.source.split(Formatter.NL)[node.start_point[0]]
-            line = line.decode('UTF-8')
-            lineno = node.start_point[0]
-            break
+    def get_diagnostics(self):
+        """Returns all parsing problems in the script.
+
+        This returns a list of zeekscript.Diagnostic instances, one for each
+        node that get_error() would consider, in the order they appear in the
+        script. Problems nested inside an already reported one do not get
+        reported separately. The list is empty when the script parsed cleanly.
+        """
+        assert self.root is not None, 'call Script.parse() before Script.get_diagnostics()'
 
-        return line, lineno, msg
+        return [Diagnostic(msg, node.start_byte, node.end_byte,
+                           node.start_point[0], node.start_point[1], node.type)
+                for node, msg in self._find_errors()]
 
     def traverse(self, include_cst=False):
         """Depth-first iterator for the script's syntax tree.
@@ -138,6 +214,78 @@ class Script:
         for node, nesting in self.root.traverse(include_cst):
             yield node, nesting
 
+    def declarations(self):
+        """Iterator over the script's toplevel declarations.
+
+        This yields a zeekscript.Declaration for each declaration in the
+        script, in order, which helps e.g. with building a symbol outline.
+        Declarations in export blocks count as toplevel, while the blocks
+        themselves don't get reported.
+        """
+        assert self.root is not None, 'call Script.parse() before Script.declarations()'
+
+        def visit(nodes):
+            for node in nodes:
+                if node.name() != 'decl' or not node.nonerr_children:
+                    continue
+
+                decl = node.nonerr_children[0]
+
+                if decl.name() == 'export_decl':
+                    yield from visit(decl.nonerr_children)
+                else:
+                    yield self._declaration(decl)
+
+        yield from visit(self.root.nonerr_children)
+
+    def _declaration(self, decl):
+        """Helper for declarations(): returns the Declaration for a node."""
+        kind = decl.type[:-len('_decl')] if decl.type.endswith('_decl') else decl.type
+        node = decl
+
+        if decl.type == 'func_decl':
+            # <func_hdr> -> <func>, <hook>, or <event>, which has the name.
+            node = decl.has_property(lambda n: n.nonerr_children[0].nonerr_children[0])
+            if node:
+                kind = 'function' if node.type == 'func' else node.type
+
+        for child in node.nonerr_children if node else []:
+            if child.name() == 'id':
+                return Declaration(kind, self.get_content(*child.script_range()).decode(
+                    'UTF-8', errors='replace'), decl)
+
+        return Declaration(kind, None, decl)
+
+    def node_at_byte(self, offset, include_cst=False):
+        """Returns the innermost node covering the given byte offset.
+
+        This returns the zeekscript.Node whose byte range includes the offset
+        and that has no children that do too, or None when the offset lies
+        outside of the tree, for example in trailing whitespace. With
+        include_cst, the result may also be a CST node, such as a comment.
+        """
+        assert self.root is not None, 'call Script.parse() before Script.node_at_byte()'
+
+        result = None
+
+        # Traversal is depth-first, so later matches are nested deeper.
+        for node, _ in self.root.traverse(include_cst):
+            if node.start_byte <= offset < node.end_byte:
+                result = node
+
+        return result
+
+    def get_original_content(self):
+        """Returns the script's content as read, or None before reading.
+
+        Unlike get_content(), this includes any byte order mark preceding the
+        script, so it's suitable for comparing with formatting results.
+        """
+        if self.source is None:
+            return None
+
+        return self.bom + self.source
+
     def __getitem__(self, key):
         """Accessor to the script source text.
 
@@ -158,20 +306,56 @@ class Script:
 
         return self.source[start_byte:end_byte]
 
-    def format(self, output=None, enable_linebreaks=True):
+    def format(self, output=None, enable_linebreaks=True, options=None):
         """Formats the script and writes out the result.
 
         The output destination can be one of three things: a filename, a file
         object, or None, which means stdout. enable_linebreaks, True by default,
-        controls whether to use linebreaks at all.
+        controls whether to use linebreaks at all. options, a
+        zeekscript.FormatOptions instance, provides full control over the
+        formatting and supersedes enable_linebreaks when provided.
+
+        Raises zeekscript.ParserError when the parse tree has errors and the
+        options do not tolerate them. The exception reports the location and
+        content of the first problem in the script. When they tolerate them
+        and request passthrough_on_error, this writes out the original script
+        content unchanged. When the options request
+        idempotency checking, raises zeekscript.IdempotencyError if formatting
+        the result once more changes it. Nothing gets written in that case.
+        Raises zeekscript.EncodingError, before writing anything, when the
+        output is a text stream and the script isn't valid UTF-8.
         """
         assert self.root is not None, 'call Script.parse() before Script.format()'
 
+        if options is None:
+            options = FormatOptions(enable_linebreaks=enable_linebreaks)
+
+        if not options.tolerate_parsing_errors and self.has_error():
+            raise self._parser_error()
+
         def do_format(out):
-            with OutputStream(out, enable_linebreaks) as ostream:
-                fclass = Formatter.lookup(self.root)
-                formatter = fclass(self, self.root, ostream)
-                formatter.format()
+            if isinstance(out, io.TextIOBase):
+                # Text streams need valid UTF-8. Report where it's not, before
+                # writing anything.
+                try:
+                    self.get_original_content().decode('UTF-8')
+                except UnicodeDecodeError as err:
+                    raise EncodingError(err.start) from err
+
+            if options.passthrough_on_error and self.has_error():
+                write_bytes(out, self.bom + self.source)
+                return
+
+            if self.bom and options.keep_byte_order_mark:
+                write_bytes(out, self.bom)
+
+            if not options.check_idempotency:
+                self._format_to(out, options)
+                return
+
+            result = self._format_checked(options)
+
+            write_bytes(out, result)
 
         if output is None:
             do_format(sys.stdout)
@@ -250,6 +434,203 @@ class Script:
             # output should be a file-like object
             do_traverse(output)
 
+    def _parser_error(self):
+        """Returns a zeekscript.ParserError describing the tree's problems."""
+        node, msg = self._find_error()
+        if node is None:
+            return ParserError('parse tree has errors')
+        context = self.source[node.start_byte:node.end_byte]
+        return ParserError(msg, self.get_diagnostics(),
+                           context=context.decode('UTF-8', errors='replace'))
+
+    def _format_to(self, out, options):
+        """Formats the script into the given file-like object.
+
+        This streams the output directly unless line-based post-processing
+        (alignment, formatting directives, end-of-script handling, shebang
+        lines, line ending conversion) requires the full result first.
+        """
+        line_ending = options.line_ending.resolve(self.source)
+
+        if not (options.align_table_values or options.align_field_comments or
+                options.sort_loads or
+                options.reflow_doc_comments or
+                not options.final_newline or line_ending != Formatter.NL or
+                FORMAT_DIRECTIVE.search(self.source) or self.source.startswith(b'#!')):
+            with OutputStream(out, options=options) as ostream:
+                fclass = Formatter.lookup(self.root)
+                formatter = fclass(self, self.root, ostream)
+                formatter.format()
+            return
+
+        buf = io.BytesIO()
+        with OutputStream(buf, options=options) as ostream:
+            fclass = Formatter.lookup(self.root)
+            formatter = fclass(self, self.root, ostream)
+            formatter.format()
+
+        result = buf.getvalue()
+
+        if options.align_table_values:
+            result = _align_assignments(result)
+
+        if options.align_field_comments:
+            result = _align_field_comments(result, options)
+
+        if options.sort_loads:
+            result = _sort_loads(result)
+
+        if options.reflow_doc_comments:
+            result = _reflow_doc_comments(result, options)
+
+        result = self._apply_directives(result)
+
+        # The formatters never produce trailing blank lines, but regions with
+        # formatting disabled may. Exactly one newline ends the script, unless
+        # none is desired. Empty scripts stay empty.
+        result = result.rstrip()
+        if result and options.final_newline:
+            result += Formatter.NL
+
+        result = self._restore_shebang(result)
+
+        # The formatters always produce Formatter.NL line endings.
+        if line_ending != Formatter.NL:
+            result = result.replace(Formatter.NL, line_ending)
+
+        write_bytes(out, result)
+
+    def _restore_shebang(self, result):
+        """Restores a shebang line starting the script, such as
+        "#!/usr/bin/env zeek", to its original content.
+
+        The formatters treat such a line like any other comment. Its content
+        matters to the system running the script though, so this ensures it
+        remains byte-identical, including any trailing whitespace.
+        """
+        if not self.source.startswith(b'#!') or not result.startswith(b'#!'):
+            return result
+
+        shebang = self.source.splitlines()[0]
+        return shebang + result[len(result.splitlines()[0]):]
+
+    def _apply_directives(self, result):
+        """Restores the original content of regions with formatting disabled.
+
+        Lines consisting only of a "# zeekfmt: off" comment disable formatting,
+        and "# zeekfmt: on" comments re-enable it. "# zeek-format: off" and
+        "# zeek-format: on" work the same way. Offs nest: it takes as many
+        ons to re-enable formatting. An on without a preceding off has no
+        effect, and an off without a matching on disables formatting for the
+        rest of the script. The directive lines themselves remain unformatted.
+
+        The formatter preserves comments and their order, so this maps the
+        directives in the formatted result to those in the source, and then
+        swaps in the source's lines between each off and its on. If the two
+        don't line up, for example because a directive ended up sharing a line
+        with other content, this returns the formatted result unchanged.
+        """
+        src_lines = self.source.splitlines()
+        res_lines = result.splitlines()
+
+        src_regions = _disabled_regions(src_lines)
+        res_regions = _disabled_regions(res_lines)
+
+        if not src_regions or len(src_regions) != len(res_regions):
+            return result
+
+        lines = []
+        res_idx = 0
+
+        for (src_start, src_end), (res_start, res_end) in zip(src_regions, res_regions):
+            lines.extend(res_lines[res_idx:res_start])
+            lines.extend(src_lines[src_start:src_end])
+            res_idx = res_end
+
+        lines.extend(res_lines[res_idx:])
+
+        out = Formatter.NL.join(lines)
+        if result.endswith(Formatter.NL):
+            out += Formatter.NL
+
+        return out
+
+    def _format_checked(self, options):
+        """Formats the script twice, verifying that the second pass is a no-op.
+
+        Returns the formatted script as bytes, or raises
+        zeekscript.IdempotencyError with both passes' results. When the first
+        pass leaves the script unchanged, the second one would just repeat
+        it, so this skips it. That makes checks of formatted scripts, the
+        common case, as fast as formatting them.
+        """
+        buf = io.BytesIO()
+        self._format_to(buf, options)
+        pass1 = buf.getvalue()
+
+        if pass1 == self.source:
+            return pass1
+
+        # The second pass needs a fresh parse of the first pass's output.
+        options = copy.copy(options)
+        options.check_idempotency = False
+
+        script = Script(io.BytesIO(pass1))
+        script.parse()
+        buf = io.BytesIO()
+        script._format_to(buf, options)
+        pass2 = buf.getvalue()
+
+        if pass1 != pass2:
+            raise IdempotencyError(pass1, pass2)
+
+        return pass1
+
+    def _find_error(self):
+        """Locates the first parsing problem in the tree.
+
+        This returns a tuple of the zeekscript.Node that introduced the problem
+        (see get_error() for details) and an error message explaining it, or
+        (None, None) when there's no such node.
+        """
+        return next(self._find_errors(), (None, None))
+
+    def _find_errors(self):
+        """Generator for all parsing problems in the tree.
+
+        This yields tuples as returned by _find_error(), for every problem in
+        the tree, skipping those in subtrees of nodes already yielded.
+        """
+        reported = set()
+
+        for node, _ in self.root.traverse():
+            ancestor = node.parent
+            while ancestor is not None and id(ancestor) not in reported:
+                ancestor = ancestor.parent
+            if ancestor is not None:
+                continue
+
+            snippet = self.source[node.start_byte:node.end_byte]
+            if len(snippet) > 50:
+                snippet = snippet[:50] + b'[...]'
+
+            if node.type == 'ERROR':
+                msg = 'cannot parse line {}, col {}: "{}"'.format(
+                    node.start_point[0], node.start_point[1],
+                    snippet.decode('UTF-8', errors='replace'))
+            elif node.is_missing:
+                msg = 'missing grammar node "{}" on line {}, col {}'.format(
+                    node.type, node.start_point[0], node.start_point[1])
+            elif node.has_error and (not node.children or
+                                     not any((kid.has_error for kid in node.children))):
+                msg = 'grammar node "{}" has error on line {}, col {}'.format(
+                    node.type, node.start_point[0], node.start_point[1])
+            else:
+                continue
+
+            reported.add(id(node))
+            yield node, msg
+
     def _clone_tree(self):
         """Deep-copy the TS tree to one consisting of zeekscript.Node instances.
 
@@ -443,3 +824,1217 @@ class Script:
 
                 node.children[-1].next_cst_siblings += node.next_cst_siblings
                 node.next_cst_siblings = []
+
+
+def format_bytes(source, options=None):
+    """Formats the given script content and returns the result.
+
+    source is the script's content, as bytes or string, and options an optional
+    zeekscript.FormatOptions instance. Returns the formatted script as bytes.
+    Raises zeekscript.ParserError like Script.parse() and Script.format() do.
+    """
+    if isinstance(source, str):
+        source = source.encode('UTF-8')
+
+    script = Script(io.BytesIO(source))
+    script.parse()
+
+    buf = io.BytesIO()
+    script.format(buf, options=options)
+    return buf.getvalue()
+
+
+class FormatCache:
+    """Remembers formatting results, to skip formatting them once more.
+
+    Editors keep formatting scripts on save, and most of the time the script
+    is unchanged since the previous run. A FormatCache remembers a digest of
+    the last result of its format_bytes() for each set of options, and returns
+    a script matching it right away. Results of scripts with parsing problems
+    aren't remembered. Each caller keeps its own cache: the module-level
+    format_bytes() and format_to_fixpoint() never consult one, and neither
+    does this when the options ask for idempotency checks.
+    """
+    def __init__(self):
+        self._digests = {} # Sorted option items -> SHA-256 digest of result
+
+    def format_bytes(self, source, options=None):
+        """Formats the given script content and returns the result.
+
+        This works like the module-level format_bytes(), skipping the work for
+        the last result this cache produced with the same options.
+        """
+        if isinstance(source, str):
+            source = source.encode('UTF-8')
+
+        options = options or FormatOptions()
+        key = tuple(sorted(vars(options).items()))
+        digest = hashlib.sha256(source).digest()
+
+        if not options.check_idempotency and self._digests.get(key) == digest:
+            return source
+
+        script = Script(io.BytesIO(source))
+        script.parse()
+
+        buf = io.BytesIO()
+        script.format(buf, options=options)
+        result = buf.getvalue()
+
+        if script.has_error():
+            self._digests.pop(key, None)
+        else:
+            self._digests[key] = hashlib.sha256(result).digest()
+
+        return result
+
+
+def format_to_fixpoint(source, max_iterations=10, options=None):
+    """Formats the given script content repeatedly until it no longer changes.
+
+    Formatting should be idempotent, but bugs can make the formatter need more
+    than one pass. This keeps formatting its own output until two consecutive
+    passes agree, making at most max_iterations passes, at least two. source
+    and options work as for format_bytes(), except that this ignores the
+    check_idempotency option. Returns the stable result as bytes. Raises
+    zeekscript.IdempotencyError when the passes don't stabilize in time,
+    ValueError for fewer than two iterations, and zeekscript.ParserError like
+    format_bytes() does.
+    """
+    if max_iterations < 2:
+        raise ValueError('need at least two formatting passes, not {}'.format(max_iterations))
+
+    options = copy.copy(options or FormatOptions())
+    options.check_idempotency = False
+
+    prev = None
+    result = format_bytes(source, options)
+
+    for _ in range(max_iterations - 1):
+        prev, result = result, format_bytes(result, options)
+        if prev == result:
+            return result
+
+    raise IdempotencyError(prev, result, iterations=max_iterations)
+
+
+# The node kinds that format_node() supports.
+_FRAGMENT_DECL_KINDS = (
+    'decl', 'module_decl', 'export_decl', 'global_decl', 'const_decl',
+    'option_decl', 'redef_decl', 'redef_enum_decl', 'redef_record_decl',
+    'type_decl', 'func_decl')
+_FRAGMENT_KINDS = _FRAGMENT_DECL_KINDS + ('stmt', 'stmt_list', 'expr')
+
+# The declaration that format_node() wraps expressions into.
+_FRAGMENT_EXPR_PREFIX = b'global __fragment ='
+
+def format_node(kind, source, options=None):
+    """Formats a script fragment consisting of a single node of the given kind.
+
+    This helps tools that synthesize or rewrite pieces of scripts, such as a
+    single function or statement, and want these formatted without having a
+    surrounding script. kind names the fragment's grammar symbol:
+
+    - "decl", or one of the specific declarations: "module_decl",
+      "export_decl", "global_decl", "const_decl", "option_decl",
+      "redef_decl", "redef_enum_decl", "redef_record_decl", "type_decl", or
+      "func_decl".
+
+    - "stmt" for a single statement, or "stmt_list" for any number of them.
+      These format as they would in a function body, minus its indentation.
+
+    - "expr" for an expression.
+
+    source and options work as for format_bytes(). Returns the formatted
+    fragment as bytes. It ends in a newline, except for expressions. Raises
+    ValueError for unsupported kinds and zeekscript.ParserError when the
+    fragment doesn't parse cleanly as the requested kind.
+    """
+    if kind not in _FRAGMENT_KINDS:
+        raise ValueError('unsupported node kind "{}", expected one of {}'.format(
+            kind, ', '.join(_FRAGMENT_KINDS)))
+
+    if isinstance(source, str):
+        source = source.encode('UTF-8')
+
+    # Wrap statements and expressions into just enough script to parse.
+    if kind in ('stmt', 'stmt_list'):
+        content = b'event zeek_init()\n{\n' + source + b'\n}\n'
+    elif kind == 'expr':
+        content = _FRAGMENT_EXPR_PREFIX + b' ' + source + b';\n'
+    else:
+        content = source
+
+    script = Script(io.BytesIO(content))
+    script.parse()
+
+    if script.has_error() or not _is_fragment(script.root, kind):
+        raise ParserError('cannot parse fragment as {}'.format(kind))
+
+    options = options or FormatOptions()
+    buf = io.BytesIO()
+    script.format(buf, options=options)
+    result = buf.getvalue()
+
+    if kind == 'expr':
+        # Strip the declaration around the expression, and its ';'.
+        return result.rstrip()[len(_FRAGMENT_EXPR_PREFIX):-1].strip()
+
+    if kind in ('stmt', 'stmt_list'):
+        # Strip the event handler's header, its braces, and the body's
+        # indentation. Depending on the brace style, the opening brace may be
+        # on the header's line.
+        lines = result.splitlines(keepends=True)[1:-1]
+        if lines and lines[0].strip() == b'{':
+            lines = lines[1:]
+
+        indent = options.get_indent()
+        return b''.join(line[len(indent):] if line.startswith(indent) else line
+                        for line in lines)
+
+    return result
+
+
+def _is_fragment(root, kind):
+    """Helper for format_node(): returns True if the given parse tree of a
+    fragment and its scaffolding has the expected structure."""
+    if len(root.nonerr_children) != 1 or not root.nonerr_children[0].nonerr_children:
+        return False
+
+    decl = root.nonerr_children[0].nonerr_children[0]
+
+    if kind == 'expr':
+        return decl.name() == 'global_decl'
+
+    if kind in ('stmt', 'stmt_list'):
+        # <func_decl> -> <func_body> -> '{' <stmt_list> '}'
+        stmts = decl.has_property(lambda n: n.nonerr_children[-1].nonerr_children[1].nonerr_children)
+        if not stmts:
+            return False
+        stmts = [node for node in stmts if node.name() == 'stmt']
+        return len(stmts) == 1 if kind == 'stmt' else len(stmts) > 0
+
+    return kind == 'decl' or decl.name() == kind
+
+
+def parse_bytes(source):
+    """Parses the given script content and returns the resulting Script.
+
+    source is the script's content, as bytes or string. The returned
+    zeekscript.Script provides the parse tree via its traverse(), root, and
+    ts_tree members. Use Script.has_error() and related methods to check for
+    parsing problems. Raises zeekscript.ParserError when the script doesn't
+    parse at all.
+    """
+    if isinstance(source, str):
+        source = source.encode('UTF-8')
+
+    script = Script(io.BytesIO(source))
+    script.parse()
+    return script
+
+
+def parse_errors(source):
+    """Returns all parsing problems in the given script content.
+
+    source is the script's content, as bytes or string. Returns a list of
+    zeekscript.Diagnostic instances as Script.get_diagnostics() does, empty
+    when the script parses cleanly. This only parses, so it's cheaper than
+    format_with_diagnostics(). Raises zeekscript.ParserError when the script
+    doesn't parse at all.
+    """
+    return parse_bytes(source).get_diagnostics()
+
+
+def is_valid_syntax(source):
+    """Predicate, returns True if the given script content parses cleanly.
+
+    source is the script's content, as bytes or string. Unlike parse_errors(),
+    this never raises zeekscript.ParserError, returning False instead.
+    """
+    try:
+        return not parse_bytes(source).has_error()
+    except ParserError:
+        return False
+
+
+def is_formatted(source, options=None):
+    """Predicate, returns True if the given script content is already formatted.
+
+    source and options work as for format_bytes(). The comparison of the script
+    with its formatted version is byte-exact, so a missing trailing newline
+    renders the script unformatted. Empty and whitespace-only scripts count as
+    formatted: the formatter reduces them to nothing, but there's nothing to
+    format in them either. Raises the same exceptions as format_bytes().
+    """
+    if isinstance(source, str):
+        source = source.encode('UTF-8')
+
+    if not source.strip():
+        return True
+
+    return _is_formatted_as(source, format_bytes(source, options))
+
+
+def _is_formatted_as(source, result):
+    """Helper for is_formatted() and zeek-format --check: returns True when
+    the given formatting result leaves the script content unchanged."""
+    return result == source or not source.strip()
+
+
+def _align_assignments(result):
+    """Helper for Script._format_to(): aligns the "=" of consecutive lines in
+    multi-line table initializers.
+
+    This applies to lines that start with a table index ("[...]") followed by
+    " =". Runs of such lines at the same indentation get their keys padded so
+    the "=" line up, going by the keys' display width. Any other line,
+    including blank ones, record fields, and those of nested blocks, ends a
+    run.
+    """
+    lines = result.split(Formatter.NL)
+    run = [] # List of (line index, regex match) tuples for the current run
+
+    def flush_run():
+        if len(run) > 1:
+            width = max(_display_width(match.group(2)) for _, match in run)
+            for idx, match in run:
+                pad = b' ' * (width - _display_width(match.group(2)))
+                lines[idx] = (match.group(1) + match.group(2) + pad +
+                              match.group(3) + match.group(4))
+        run.clear()
+
+    for idx, line in enumerate(lines):
+        match = ALIGNABLE_ASSIGNMENT.match(line)
+        if match and (not run or run[0][1].group(1) == match.group(1)):
+            run.append((idx, match))
+            continue
+
+        flush_run()
+        if match:
+            run.append((idx, match))
+
+    flush_run()
+
+    return Formatter.NL.join(lines)
+
+
+def _display_width(data):
+    """Returns the number of columns the given UTF-8 bytes take up on screen.
+
+    Wide East Asian characters take two columns, combining characters none.
+    """
+    width = 0
+    for char in data.decode('UTF-8', errors='replace'):
+        if unicodedata.combining(char):
+            continue
+        width += 2 if unicodedata.east_asian_width(char) in ('W', 'F') else 1
+    return width
+
+
+def _align_field_comments(result, options):
+    """Helper for Script._format_to(): aligns the ##< comments of consecutive
+    lines to a common column.
+
+    A group consists of consecutive lines at the same indentation, plus any
+    lines that continue a ##< comment. Blank lines and any other change in
+    indentation end it. The comments go one space past the group's longest
+    line that carries one, unless that line reaches max_line_len.
+    """
+    lines = result.split(Formatter.NL)
+    group = [] # List of (line index, regex match, is continuation) tuples
+    indent = None # Indentation of the current group's lines
+
+    def width(data):
+        return len(data.expandtabs(options.tab_size))
+
+    def flush_group():
+        widths = [width(match.group(1) + match.group(2)) for _, match, cont in group
+                  if not cont]
+        widths = [w for w in widths if w < options.max_line_len]
+        column = max(widths) + 1 if widths else None
+        comment_column = None # Column of the comment that continuations follow
+
+        for idx, match, cont in group:
+            if not cont:
+                code = match.group(1) + match.group(2)
+                comment_column = width(code) + 1
+                if column is not None and comment_column <= column:
+                    comment_column = column
+                lines[idx] = code + b' ' * (comment_column - width(code)) + match.group(3)
+            elif comment_column is not None:
+                lines[idx] = (indent + b' ' * (comment_column - width(indent)) +
+                              match.group(2))
+
+        group.clear()
+
+    for idx, line in enumerate(lines):
+        cont = CONTINUED_DOC_COMMENT.match(line)
+
+        if cont and indent is not None and cont.group(1).startswith(indent):
+            group.append((idx, cont, True))
+            continue
+
+        line_indent = line[:len(line) - len(line.lstrip(b' \t'))]
+
+        if not line.strip() or line_indent != indent:
+            flush_group()
+            indent = line_indent if line.strip() else None
+
+        match = TRAILING_DOC_COMMENT.match(line)
+        if match:
+            group.append((idx, match, False))
+
+    flush_group()
+
+    return Formatter.NL.join(lines)
+
+
+def _sort_loads(result):
+    """Helper for Script._format_to(): sorts runs of consecutive @load lines.
+
+    A run consists of toplevel directives of the same kind (@load, @load-plugin,
+    or @load-sigs) on consecutive lines. Any other line, including blank lines,
+    comments, and other kinds of loads, ends it, since those usually group the
+    loads intentionally. Each line moves as a whole, so comments trailing a
+    directive stay with it. Sorting is lexical by the loaded path. Of
+    identical lines in a run, only the first remains.
+    """
+    lines = []
+    run = [] # The lines of the current run
+    directive = None # The directive making up the current run
+
+    def sort_key(line):
+        return (line[len(directive):].split(b'#', 1)[0].strip(), line)
+
+    for line in result.split(Formatter.NL) + [b'']:
+        match = LOAD_DIRECTIVE.match(line)
+
+        if match and match.group(1) == directive:
+            run.append(line)
+            continue
+
+        # dict.fromkeys() drops repeated lines, keeping the order.
+        lines.extend(sorted(dict.fromkeys(run), key=sort_key))
+        run, directive = [], None
+
+        if match:
+            run, directive = [line], match.group(1)
+        else:
+            lines.append(line)
+
+    return Formatter.NL.join(lines[:-1])
+
+
+def _reflow_doc_comments(result, options):
+    """Helper for Script._format_to(): rewraps the text of ## and ##!
+    documentation comments to fit the maximum line length.
+
+    A paragraph consists of consecutive comment lines with the same
+    indentation and marker. Blank comment lines, lines the
+    DOC_COMMENT_VERBATIM regex matches, and any other line end it, and lines
+    the DOC_COMMENT_PARAM regex matches start a new one. Words that are too
+    long for a line by themselves get a line of their own.
+    """
+    lines = result.split(Formatter.NL)
+    out = []
+    para = [] # Words of the current paragraph
+    prefix = None # Indentation and marker of the current paragraph
+
+    def flush_para():
+        nonlocal prefix
+        if para:
+            indent = len(prefix.expandtabs(options.tab_size))
+            width = max(options.max_line_len - indent - 1, 1)
+            line = []
+            for word in para:
+                if line and len(b' '.join(line + [word])) > width:
+                    out.append(prefix + b' ' + b' '.join(line))
+                    line = []
+                line.append(word)
+            out.append(prefix + b' ' + b' '.join(line))
+        para.clear()
+        prefix = None
+
+    for line in lines:
+        match = DOC_COMMENT.match(line)
+        text = match.group(3) if match else b''
+
+        if not text.strip() or DOC_COMMENT_VERBATIM.match(text):
+            # Not a comment we reflow: keep the line as it is.
+            flush_para()
+            out.append(line)
+            continue
+
+        line_prefix = match.group(1) + match.group(2)
+        if line_prefix != prefix or DOC_COMMENT_PARAM.match(text):
+            flush_para()
+            prefix = line_prefix
+
+        para.extend(text.split())
+
+    flush_para()
+
+    return Formatter.NL.join(out)
+
+
+def _disabled_regions(lines):
+    """Helper for Script._apply_directives(): returns a list of (start, end)
+    line index ranges, slice-style, of regions disabled via zeekfmt
+    directives, including the directive lines."""
+    regions = []
+    depth, start = 0, None
+
+    for idx, line in enumerate(lines):
+        match = FORMAT_DIRECTIVE.match(line)
+        if not match:
+            continue
+        if match.group(1) == b'off':
+            if depth == 0:
+                start = idx
+            depth += 1
+        elif depth > 0:
+            depth -= 1
+            if depth == 0:
+                regions.append((start, idx + 1))
+
+    if depth > 0:
+        regions.append((start, len(lines)))
+
+    return regions
+
+
+def format_diff(source, filename='-', options=None):
+    """Returns a unified diff of the changes formatting would make to a script.
+
+    source and options work as for format_bytes(). filename is the name to
+    report in the diff's "---" and "+++" headers. Returns None when the script
+    is already formatted, and otherwise the diff as a string, with three lines
+    of context around each hunk. Raises the same exceptions as
+    format_bytes().
+    """
+    if isinstance(source, str):
+        source = source.encode('UTF-8')
+
+    return _unified_diff(source, format_bytes(source, options), filename)
+
+
+def _unified_diff(source, result, filename):
+    """Helper for format_diff() and zeek-format --diff: returns a unified diff
+    from the source to the formatting result, or None if the result doesn't
+    change anything."""
+    if _is_formatted_as(source, result):
+        return None
+
+    def lines(content):
+        # difflib needs every line terminated, so flag a missing final newline
+        # the way diff(1) does.
+        res = content.decode('UTF-8', errors='replace').splitlines(keepends=True)
+        if res and not res[-1].endswith(('\n', '\r')):
+            res[-1] += '\n\\ No newline at end of file\n'
+        return res
+
+    return ''.join(difflib.unified_diff(
+        lines(source), lines(result), 'a/' + filename, 'b/' + filename))
+
+
+class Position:
+    """A location in a script, as used by zeekscript.TextEdit.
+
+    line is the 0-based line number, and character the 0-based offset in that
+    line, counted in UTF-16 code units as the Language Server Protocol does.
+    """
+    def __init__(self, line, character=0):
+        self.line = line
+        self.character = character
+
+    def __eq__(self, other):
+        if not isinstance(other, Position):
+            return NotImplemented
+        return vars(self) == vars(other)
+
+    def __repr__(self):
+        return 'Position({}, {})'.format(self.line, self.character)
+
+
+class TextEdit:
+    """One of the edits format_edits() returns.
+
+    new_text is a string that replaces the script's content from start up to
+    (but excluding) end, both zeekscript.Position instances. This mirrors the
+    Language Server Protocol's TextEdit.
+    """
+    def __init__(self, start, end, new_text):
+        self.start = start
+        self.end = end
+        self.new_text = new_text
+
+    def __eq__(self, other):
+        if not isinstance(other, TextEdit):
+            return NotImplemented
+        return vars(self) == vars(other)
+
+    def __repr__(self):
+        return 'TextEdit({!r}, {!r}, {!r})'.format(self.start, self.end, self.new_text)
+
+
+def format_edits(source, options=None):
+    """Returns the changes formatting would make to a script, as text edits.
+
+    source and options work as for format_bytes(). Instead of the formatted
+    script, this returns a list of zeekscript.TextEdit instances that turn the
+    script into it, in order and without overlap. The edits replace full
+    lines and cover only those that formatting changes, which suits language
+    servers: editors keep cursors, selections, and undo history for the rest
+    of the script. The list is empty when the script is already formatted.
+    Raises the same exceptions as format_bytes().
+    """
+    if isinstance(source, str):
+        source = source.encode('UTF-8')
+
+    result = format_bytes(source, options)
+
+    if _is_formatted_as(source, result):
+        return []
+
+    # bytes.splitlines() breaks lines just like LSP does, at \n, \r\n, and \r.
+    src_lines = source.splitlines(keepends=True)
+    res_lines = result.splitlines(keepends=True)
+
+    def position(idx):
+        # A position at the start of the given line. Past a final line that
+        # lacks a newline, that's the end of that line instead.
+        if idx == len(src_lines) and src_lines and not src_lines[-1].endswith((b'\n', b'\r')):
+            last = src_lines[-1].decode('UTF-8', errors='replace')
+            return Position(idx - 1, len(last.encode('UTF-16-LE')) // 2)
+        return Position(idx)
+
+    edits = []
+    matcher = difflib.SequenceMatcher(None, src_lines, res_lines, autojunk=False)
+
+    for tag, src1, src2, res1, res2 in matcher.get_opcodes():
+        if tag != 'equal':
+            new_text = b''.join(res_lines[res1:res2]).decode('UTF-8', errors='replace')
+            edits.append(TextEdit(position(src1), position(src2), new_text))
+
+    return edits
+
+
+def format_with_diagnostics(source, options=None):
+    """Formats the given script content and reports all of its parsing problems.
+
+    source and options work as for format_bytes(). Returns a tuple of the
+    formatted script as bytes and a list of zeekscript.Diagnostic instances,
+    one for each problem, as Script.get_diagnostics() reports them. When the
+    options do not tolerate parsing errors and there are some, the formatted
+    result is None, but the diagnostics remain available. Raises
+    zeekscript.ParserError when the script doesn't parse at all.
+    """
+    if isinstance(source, str):
+        source = source.encode('UTF-8')
+
+    script = Script(io.BytesIO(source))
+    script.parse()
+    diagnostics = script.get_diagnostics()
+
+    buf = io.BytesIO()
+
+    try:
+        script.format(buf, options=options)
+    except ParserError:
+        return None, diagnostics
+
+    return buf.getvalue(), diagnostics
+
+
+class RangeEdit:
+    """The result of format_range(): an edit to apply to the script.
+
+    new_text is the formatted content, as bytes, that replaces the script's
+    content from byte offset actual_start up to (but excluding) actual_end.
+    """
+    def __init__(self, new_text, actual_start, actual_end):
+        self.new_text = new_text
+        self.actual_start = actual_start
+        self.actual_end = actual_end
+
+    def apply(self, source):
+        """Returns the given script content, as bytes, with the edit applied."""
+        if isinstance(source, str):
+            source = source.encode('UTF-8')
+        return source[:self.actual_start] + self.new_text + source[self.actual_end:]
+
+    def __eq__(self, other):
+        if not isinstance(other, RangeEdit):
+            return NotImplemented
+        return vars(self) == vars(other)
+
+    def __repr__(self):
+        return 'RangeEdit({!r}, {}, {})'.format(
+            self.new_text, self.actual_start, self.actual_end)
+
+
+def format_range(source, start_byte, end_byte, options=None):
+    """Formats only the part of a script covering the given byte range.
+
+    source and options work as for format_bytes(). start_byte and end_byte
+    delineate the selection, behaving like slice indices. Since only complete
+    constructs can be formatted, this expands the selection to the full lines
+    of the statements it touches in the innermost statement list containing
+    it, such as an event handler's body. When there is none, or those
+    statements share lines with other code, this falls back to the toplevel
+    constructs (declarations, statements, etc) the selection touches. Returns a
+    zeekscript.RangeEdit describing the expanded range and its formatted
+    replacement, which keeps the indentation of the surrounding code. When the
+    selection touches no such constructs, the edit changes nothing.
+
+    This formats the whole script, parses the result, and relates constructs
+    by their position in the parse trees. When the two trees don't line up,
+    the edit covers the whole script. Since a broken script could misplace the
+    boundaries of the selected constructs, this requires a script without
+    parsing problems, raising zeekscript.ParserError otherwise. Raises
+    ValueError for a selection outside of the script, and otherwise the same
+    exceptions as format_bytes().
+    """
+    if isinstance(source, str):
+        source = source.encode('UTF-8')
+
+    if not 0 <= start_byte <= end_byte <= len(source):
+        raise ValueError('invalid range {}-{} in script of {} bytes'.format(
+            start_byte, end_byte, len(source)))
+
+    script = Script(io.BytesIO(source))
+    if not script.parse():
+        raise script._parser_error()
+
+    # The edit never covers a byte order mark, so keep any in place.
+    options = copy.copy(options or FormatOptions())
+    options.keep_byte_order_mark = True
+
+    buf = io.BytesIO()
+    script.format(buf, options=options)
+    result = buf.getvalue()
+
+    # Node offsets don't account for a byte order mark, and formatting keeps
+    # any such mark, so both versions of the script start with it.
+    bom = len(script.bom)
+    sel_start, sel_end = max(start_byte - bom, 0), max(end_byte - bom, 0)
+
+    formatted = Script(io.BytesIO(result))
+    formatted.parse()
+
+    is_cursor = start_byte == end_byte
+
+    # Prefer the statements in the innermost statement list containing the
+    # selection, such as an event handler's body, working outward from there.
+    for src_nodes, res_nodes in _enclosing_stmt_lists(
+            script.root, formatted.root, sel_start, sel_end):
+        idxs = _touched_nodes(src_nodes, sel_start, sel_end, is_cursor)
+        if not idxs:
+            continue
+
+        first, last = _expand_to_lines(src_nodes, res_nodes, idxs[0], idxs[-1])
+
+        if (_on_own_lines(source[bom:], src_nodes[first], src_nodes[last]) and
+                _on_own_lines(result[bom:], res_nodes[first], res_nodes[last])):
+            return _range_edit(source, result, bom, src_nodes[first], src_nodes[last],
+                               res_nodes[first], res_nodes[last])
+
+    src_nodes, res_nodes = script.root.children, formatted.root.children
+
+    if len(src_nodes) != len(res_nodes):
+        return RangeEdit(result, 0, len(source))
+
+    idxs = _touched_nodes(src_nodes, sel_start, sel_end, is_cursor)
+
+    if not idxs:
+        return RangeEdit(source[start_byte:end_byte], start_byte, end_byte)
+
+    first, last = _expand_to_lines(src_nodes, res_nodes, idxs[0], idxs[-1])
+
+    return _range_edit(source, result, bom, src_nodes[first], src_nodes[last],
+                       res_nodes[first], res_nodes[last])
+
+
+def _enclosing_stmt_lists(src_root, res_root, sel_start, sel_end):
+    """Helper for format_range(): returns the statement lists containing the
+    selection, innermost first, as pairs of their statements in the original
+    and the formatted script.
+
+    Formatting leaves the tree's structure intact, so a node's counterpart in
+    the formatted script sits at the same position in its tree. Where the
+    structures differ, the search stops.
+    """
+    res = []
+    src, fmt = src_root, res_root
+
+    while True:
+        for idx, child in enumerate(src.children):
+            if child.start_byte <= sel_start and sel_end <= child.end_byte:
+                break
+        else:
+            break
+
+        if idx >= len(fmt.children) or fmt.children[idx].type != child.type:
+            break
+
+        src, fmt = child, fmt.children[idx]
+
+        if src.type == 'stmt_list' and len(src.children) == len(fmt.children):
+            res.append((src.children, fmt.children))
+
+    return reversed(res)
+
+
+def _touched_nodes(nodes, sel_start, sel_end, is_cursor):
+    """Helper for format_range(): returns the indexes of the nodes that the
+    selection touches. A cursor touches the nodes it sits in or next to."""
+    if is_cursor:
+        return [idx for idx, node in enumerate(nodes)
+                if node.start_byte <= sel_start <= node.end_byte]
+
+    return [idx for idx, node in enumerate(nodes)
+            if node.start_byte < sel_end and node.end_byte > sel_start]
+
+
+def _expand_to_lines(src_nodes, res_nodes, first, last):
+    """Helper for format_range(): returns the first and last index of the node
+    sequence to format, widening the given one to cover full lines.
+
+    Since we replace full lines, this pulls in any neighbors sharing them, in
+    either version of the script.
+    """
+    def shares_line(nodes, idx1, idx2):
+        return nodes[idx1].end_point[0] >= nodes[idx2].start_point[0]
+
+    changed = True
+    while changed:
+        changed = False
+        for nodes in (src_nodes, res_nodes):
+            if first > 0 and shares_line(nodes, first - 1, first):
+                first -= 1
+                changed = True
+            if last < len(nodes) - 1 and shares_line(nodes, last, last + 1):
+                last += 1
+                changed = True
+
+    return first, last
+
+
+def _on_own_lines(content, first, last):
+    """Helper for format_range(): returns True if the lines spanned by the
+    given nodes contain nothing else, aside from whitespace and a trailing
+    comment. Otherwise, as in "{ print 1; }", replacing those lines would
+    affect surrounding code."""
+    start = content.rfind(b'\n', 0, first.start_byte) + 1
+    end = content.find(b'\n', last.end_byte)
+    if end < 0:
+        end = len(content)
+
+    return (not content[start:first.start_byte].strip() and
+            content[last.end_byte:end].strip()[:1] in (b'', b'#'))
+
+
+def _range_edit(source, result, bom, src_first, src_last, res_first, res_last):
+    """Helper for format_range(): returns the RangeEdit replacing the lines
+    spanning the given nodes in the source with those in the result."""
+    src_start, src_end = _line_span(source[bom:], src_first.start_point[0],
+                                    src_last.end_point[0])
+    res_start, res_end = _line_span(result[bom:], res_first.start_point[0],
+                                    res_last.end_point[0])
+
+    return RangeEdit(result[bom + res_start:bom + res_end], bom + src_start, bom + src_end)
+
+
+def format_lines(source, start_line, end_line, options=None):
+    """Formats only the given lines of a script.
+
+    This is a line-based convenience wrapper around format_range(). start_line
+    and end_line are 0-based and inclusive. As with format_range(), the range
+    expands to the full statements or constructs it touches, but content
+    outside of those remains byte-identical. Returns the full script, with the
+    range formatted, as bytes. Raises ValueError for lines outside of the script,
+    and otherwise the same exceptions as format_range().
+    """
+    if isinstance(source, str):
+        source = source.encode('UTF-8')
+
+    num_lines = source.count(b'\n') + 1
+    if not 0 <= start_line <= end_line < num_lines:
+        raise ValueError('invalid line range {}-{} in script of {} lines'.format(
+            start_line, end_line, num_lines))
+
+    start, end = _line_span(source, start_line, end_line)
+    return format_range(source, start, end, options).apply(source)
+
+
+def _line_span(content, first_row, last_row):
+    """Helper for format_range(): returns the byte offsets spanning the given
+    0-based rows of the content, slice-style, including the last row's line
+    ending."""
+    offset, start, end = 0, 0, len(content)
+
+    # TreeSitter counts rows by \n, so we do too.
+    for row, line in enumerate(content.split(b'\n')):
+        if row == first_row:
+            start = offset
+        offset += len(line) + 1
+        if row == last_row:
+            end = min(offset, len(content))
+            break
+
+    return start, end
+
+
+def format_stream(istream, ostream, options=None):
+    """Formats a script read from one file-like object into another.
+
+    istream provides the script content, in bytes or text. ostream receives
+    the formatted content: as text if it is a text stream (an io.TextIOBase,
+    such as sys.stdout), and as bytes otherwise. The output gets written line
+    by line as formatting progresses, without buffering up the whole result,
+    unless formatting directives or idempotency checking require it. options
+    is an optional zeekscript.FormatOptions instance. Raises the same
+    exceptions as format_bytes().
+    """
+    script = Script(istream)
+    script.parse()
+    script.format(ostream, options=options)
+
+
+class FileOutcome(enum.Enum):
+    """What format_file() did to a file when formatting it in place."""
+    UNCHANGED = enum.auto() # Already formatted, not written
+    REWRITTEN = enum.auto() # Written with the formatted content
+
+
+def format_file(path, inplace=False, options=None):
+    """Formats the script in the given file.
+
+    path is a filename string or pathlib.Path, and options an optional
+    zeekscript.FormatOptions instance. When inplace is False, this returns the
+    formatted script as bytes. When True, this writes the result back to the
+    file and returns a zeekscript.FileOutcome. The file only gets written when
+    formatting changed it, and the write is atomic: the result goes to a
+    temporary file in the same directory that then replaces the original, so a
+    failure midway doesn't truncate the script. The file keeps its
+    permissions, and for a symlink the outcome concerns the file it points to.
+
+    Raises zeekscript.FileError when the path is a directory or reading or
+    writing the file fails, and zeekscript.ParserError as format_bytes() does.
+    """
+    if os.path.isdir(path):
+        raise FileError('"{}" is a directory'.format(path))
+
+    script = Script(path)
+    script.parse()
+
+    buf = io.BytesIO()
+    script.format(buf, options=options)
+    result = buf.getvalue()
+
+    if not inplace:
+        return result
+
+    if result == script.get_original_content():
+        return FileOutcome.UNCHANGED
+
+    _write_atomically(path, result)
+    return FileOutcome.REWRITTEN
+
+
+def format_file_in_place(path, options=None):
+    """Formats the script in the given file and writes it back if needed.
+
+    This is shorthand for format_file(path, inplace=True, options=options),
+    returning True if the file changed and False if it was already formatted.
+    Raises the same exceptions as format_file().
+    """
+    return format_file(path, inplace=True, options=options) == FileOutcome.REWRITTEN
+
+
+class FormatFileResult:
+    """The outcome of formatting one of the scripts found by format_dir().
+
+    path is the script's path, changed whether formatting rewrote it, and
+    error the zeekscript.Error that prevented formatting it, or None.
+    """
+    def __init__(self, path, changed=False, error=None):
+        self.path = path
+        self.changed = changed
+        self.error = error
+
+    def __repr__(self):
+        return 'FormatFileResult({!r}, changed={!r}, error={!r})'.format(
+            self.path, self.changed, self.error)
+
+
+def format_dir(path, options=None, jobs=1):
+    """Formats all *.zeek scripts in the given directory tree in place.
+
+    path is a directory name string or pathlib.Path, and options an optional
+    zeekscript.FormatOptions instance. The scripts get formatted as in
+    format_file_in_place(), by default one after the other in this process.
+    With jobs > 1, the work gets spread across up to that many worker
+    processes, and with 0 across one per CPU. Problems with individual
+    scripts don't stop the others.
+
+    Returns a list of zeekscript.FormatFileResult instances, one per script,
+    sorted by path. Raises zeekscript.FileError when path isn't a directory.
+    """
+    if not os.path.isdir(path):
+        raise FileError('"{}" is not a directory'.format(path))
+
+    paths = sorted(_find_scripts(str(path)))
+    return _parallel_map(_format_dir_entry, paths, options, jobs)
+
+
+def _format_dir_entry(path, options):
+    """Formats a single script for format_dir(), possibly in a worker process."""
+    try:
+        return FormatFileResult(path, changed=format_file_in_place(path, options))
+    except Error as err:
+        return FormatFileResult(path, error=err)
+
+
+def format_many(sources, options=None, jobs=1):
+    """Formats a batch of script contents.
+
+    sources is an iterable of script contents, each as bytes or string, and
+    options an optional zeekscript.FormatOptions instance. By default, this
+    formats the scripts one after the other in this process. With jobs > 1,
+    the work gets spread across up to that many worker processes, and with 0
+    across one per CPU. Each worker handles many scripts, reusing its parser.
+    Problems with individual scripts don't stop the others.
+
+    Returns a list with one entry per source, in input order. Each entry is
+    either the formatted script as bytes or the zeekscript.Error that
+    format_bytes() raised for it.
+    """
+    return _parallel_map(_format_many_entry, list(sources), options, jobs)
+
+
+def _format_many_entry(source, options):
+    """Formats a single script for format_many(), possibly in a worker process."""
+    try:
+        return format_bytes(source, options)
+    except Error as err:
+        return err
+
+
+def _parallel_map(func, items, options, jobs):
+    """Returns the list of func(item, options) results for the given items.
+
+    This makes the calls in this process when jobs is 1, and otherwise spreads
+    them across up to jobs worker processes, or one per CPU when jobs is 0.
+    Worker processes cost startup time, so callers opt into them explicitly.
+    The results stay in the order of the items.
+    """
+    jobs = 1 if jobs is None else jobs or os.cpu_count() or 1
+    work = (items, [options] * len(items))
+
+    if jobs > 1 and len(items) > 1:
+        # Hand out items in batches, so large numbers of small scripts don't
+        # drown in interprocess communication.
+        chunksize = max(1, len(items) // (jobs * 4))
+
+        with concurrent.futures.ProcessPoolExecutor(max_workers=jobs) as pool:
+            # map() preserves the order of the items in its results.
+            return list(pool.map(func, *work, chunksize=chunksize))
+
+    return list(map(func, *work))
+
+
+def collect_zeek_files(root, on_ignored=None):
+    """Returns the paths of the *.zeek files in the given directory tree.
+
+    root is a directory name string or pathlib.Path. The result lists the
+    files in traversal order: each directory's files sorted by name, followed
+    by its subdirectories, also sorted. Symlinks to directories are not
+    followed.
+
+    Files and directories matched by .zeekformatignore files don't make it
+    into the result. Such files can reside at the root and in any
+    subdirectory, where they apply to that directory's subtree. They use
+    gitignore's syntax: one glob pattern per line, with "#" comments, "!" to
+    re-include a path that an earlier pattern excluded, and a trailing "/" to
+    match only directories. Patterns without a slash match names at any
+    depth. Others are relative to the ignore file's directory. On a
+    path, later patterns take precedence over earlier ones, and patterns in
+    deeper directories over those further up. As with git, no pattern can
+    re-include files inside an excluded directory.
+
+    When provided, on_ignored gets called for every excluded path, with the
+    path and a string naming the ignore file, line, and pattern responsible.
+    """
+    return list(_find_scripts(str(root), on_ignored))
+
+
+def _find_scripts(dirname, on_ignored=None):
+    """Generator for the *.zeek files in the given directory tree.
+
+    This does not follow symlinks to directories, so symlink loops cannot cause
+    infinite recursion. It honors .zeekformatignore files as described in
+    collect_zeek_files(). It skips, with a warning, any paths that aren't
+    valid UTF-8.
+    """
+    # The ignore rules in effect for each directory yet to visit.
+    rules = {dirname: []}
+
+    for dirpath, dirnames, filenames in os.walk(dirname):
+        relpath = os.path.relpath(dirpath, dirname).replace(os.sep, '/')
+        relpath = '' if relpath == '.' else relpath + '/'
+        dir_rules = rules.pop(dirpath, []) + _read_ignore_file(dirpath, relpath)
+
+        def ignored(name, is_dir):
+            reason = _ignore_reason(dir_rules, relpath + name, is_dir)
+            if reason is not None and on_ignored is not None:
+                on_ignored(os.path.join(dirpath, name), reason)
+            return reason is not None
+
+        for name in sorted(filenames):
+            if not name.endswith('.zeek') or ignored(name, False):
+                continue
+
+            path = os.path.join(dirpath, name)
+
+            try:
+                path.encode('UTF-8')
+            except UnicodeEncodeError:
+                print_error('warning: skipping {}; path is not valid UTF-8'.format(
+                    os.fsencode(path)))
+                continue
+
+            yield path
+
+        # Descend in predictable order, skipping ignored directories.
+        dirnames[:] = [name for name in sorted(dirnames) if not ignored(name, True)]
+
+        for name in dirnames:
+            rules[os.path.join(dirpath, name)] = dir_rules
+
+
+def _read_ignore_file(dirpath, relpath):
+    """Returns the rules of the ignore file in the given directory, if any.
+
+    relpath is the directory's path relative to the traversal root, in
+    "/"-separated form and with a trailing slash, or empty for the root. Each
+    rule is a tuple of the compiled pattern, whether it negates, whether it
+    only matches directories, relpath, and a description of the rule's origin.
+    """
+    fname = os.path.join(dirpath, IGNORE_FILE)
+
+    try:
+        with open(fname, 'rb') as hdl:
+            lines = hdl.read().decode('UTF-8', errors='replace').splitlines()
+    except FileNotFoundError:
+        return []
+    except OSError as err:
+        print_error('warning: cannot read {}: {}'.format(fname, err))
+        return []
+
+    rules = []
+
+    for lineno, line in enumerate(lines, start=1):
+        pattern = line.rstrip()
+        if not pattern or pattern.startswith('#'):
+            continue
+
+        negate = pattern.startswith('!')
+        if negate:
+            pattern = pattern[1:]
+        elif pattern.startswith('\\'):
+            pattern = pattern[1:] # Escaped leading "#" or "!"
+
+        dir_only = pattern.endswith('/')
+        pattern = pattern.rstrip('/')
+        if not pattern:
+            continue
+
+        rules.append((_ignore_regex(pattern), negate, dir_only, relpath,
+                      '{}:{}: {}'.format(fname, lineno, line.rstrip())))
+
+    return rules
+
+
+def _ignore_regex(pattern):
+    """Translates a gitignore-style glob pattern into a compiled regex."""
+    # Patterns with a slash are relative to the ignore file's directory, the
+    # others match at any depth.
+    res = '' if '/' in pattern else '(?:.*/)?'
+    pattern = pattern.lstrip('/')
+    idx = 0
+
+    while idx < len(pattern):
+        if pattern.startswith('**/', idx):
+            res += '(?:.*/)?'
+            idx += 3
+        elif pattern.startswith('**', idx):
+            res += '.*'
+            idx += 2
+        elif pattern[idx] == '*':
+            res += '[^/]*'
+            idx += 1
+        elif pattern[idx] == '?':
+            res += '[^/]'
+            idx += 1
+        elif pattern[idx] == '[' and ']' in pattern[idx+2:]:
+            end = pattern.index(']', idx + 2)
+            chars = pattern[idx+1:end].replace('\\', '\\\\')
+            if chars.startswith('!'):
+                chars = '^' + chars[1:]
+            res += '[' + chars + ']'
+            idx = end + 1
+        elif pattern[idx] == '\\' and idx + 1 < len(pattern):
+            res += re.escape(pattern[idx+1])
+            idx += 2
+        else:
+            res += re.escape(pattern[idx])
+            idx += 1
+
+    return re.compile(res)
+
+
+def _ignore_reason(rules, relpath, is_dir):
+    """Returns the origin of the rule that ignores the given path, or None.
+
+    relpath is relative to the traversal root, in "/"-separated form. The last
+    matching rule decides, so negated rules can re-include paths.
+    """
+    reason = None
+
+    for regex, negate, dir_only, base, origin in rules:
+        if dir_only and not is_dir:
+            continue
+        if regex.fullmatch(relpath[len(base):]):
+            reason = None if negate else origin
+
+    return reason
+
+
+def _write_atomically(path, data):
+    """Replaces the content of the given file with the given bytes.
+
+    The data first goes into a temporary file alongside the original, which
+    then gets renamed over it. The original's permissions carry over. For a
+    symlink, this replaces the file it points to and keeps the link. Raises
+    zeekscript.FileError when anything goes wrong, leaving the original intact.
+    """
+    path = os.path.realpath(str(path))
+    dirname, basename = os.path.split(path)
+    tmpname = None
+
+    try:
+        mode = os.stat(path).st_mode
+        fd, tmpname = tempfile.mkstemp(prefix='.' + basename + '.',
+                                       suffix='.tmp', dir=dirname)
+        with os.fdopen(fd, 'wb') as hdl:
+            hdl.write(data)
+            hdl.flush()
+            os.fsync(hdl.fileno())
+        os.chmod(tmpname, mode & 0o7777)
+        os.replace(tmpname, path)
+    except OSError as err:
+        if tmpname is not None and os.path.exists(tmpname):
+            os.unlink(tmpname)
+        raise FileError(str(err)) from err